@@ -1,5 +1,5 @@
 <?xml version="1.0" encoding="UTF-8"?>
-<tileset name="Tiles" tilewidth="64" tileheight="64" tilecount="3" columns="0">
+<tileset name="Tiles" tilewidth="64" tileheight="64" tilecount="12" columns="0">
  <grid orientation="orthogonal" width="1" height="1"/>
  <tile id="0">
   <properties>
@@ -19,7 +19,67 @@
   <properties>
    <property name="ladder" type="bool" value="false"/>
    <property name="solid" type="bool" value="false"/>
+   <property name="water" type="bool" value="true"/>
   </properties>
   <image width="64" height="64" source="tiles/water.png"/>
+  <animation>
+   <frame tileid="2" duration="500"/>
+   <frame tileid="3" duration="500"/>
+  </animation>
+ </tile>
+ <tile id="3">
+  <properties>
+   <property name="water" type="bool" value="true"/>
+  </properties>
+  <image width="64" height="64" source="tiles/water2.png"/>
+ </tile>
+ <tile id="4">
+  <properties>
+   <property name="one_way" type="bool" value="true"/>
+  </properties>
+  <image width="64" height="64" source="tiles/platform.png"/>
+ </tile>
+ <tile id="5">
+  <properties>
+   <property name="slope" value="rise45"/>
+  </properties>
+  <image width="64" height="64" source="tiles/slope_rise45.png"/>
+ </tile>
+ <tile id="6">
+  <properties>
+   <property name="slope" value="fall45"/>
+  </properties>
+  <image width="64" height="64" source="tiles/slope_fall45.png"/>
+ </tile>
+ <tile id="7">
+  <properties>
+   <property name="slope" value="rise22_low"/>
+  </properties>
+  <image width="64" height="64" source="tiles/slope_rise22_low.png"/>
+ </tile>
+ <tile id="8">
+  <properties>
+   <property name="slope" value="rise22_high"/>
+  </properties>
+  <image width="64" height="64" source="tiles/slope_rise22_high.png"/>
+ </tile>
+ <tile id="9">
+  <properties>
+   <property name="slope" value="fall22_high"/>
+  </properties>
+  <image width="64" height="64" source="tiles/slope_fall22_high.png"/>
+ </tile>
+ <tile id="10">
+  <properties>
+   <property name="slope" value="fall22_low"/>
+  </properties>
+  <image width="64" height="64" source="tiles/slope_fall22_low.png"/>
+ </tile>
+ <tile id="11">
+  <properties>
+   <property name="breakable" type="bool" value="true"/>
+   <property name="solid" type="bool" value="true"/>
+  </properties>
+  <image width="64" height="64" source="tiles/brick_cracked.png"/>
  </tile>
 </tileset>
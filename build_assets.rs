@@ -29,19 +29,101 @@ use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 type AtlasLocation = (f32, f32, f32, f32, u32, u32);
 
+// Must match the flags in tilemap.rs
+const LAYER_COLLISION: u32 = 1;
+const LAYER_FOREGROUND: u32 = 2;
+
+// Tile flags. Must match tilemap.rs
+const FLAG_SOLID: u8 = 1;
+const FLAG_LADDER: u8 = 2;
+const FLAG_WATER: u8 = 4;
+const FLAG_ONE_WAY: u8 = 8;
+const FLAG_BREAKABLE: u8 = 0x80;
+
+// Values of the slope tile property, in the order of the Slope enum in
+// tilemap.rs. The flags store 1 + the index here in the SLOPE_SHIFT bits.
+const SLOPE_NAMES: [&str; 6] = [
+    "rise45",
+    "fall45",
+    "rise22_low",
+    "rise22_high",
+    "fall22_high",
+    "fall22_low",
+];
+const SLOPE_SHIFT: u8 = 4;
+
+// Tiled stores flip flags in the high bits of each GID.
+const TILED_FLIP_HORIZONTAL: u32 = 0x80000000;
+const TILED_FLIP_VERTICAL: u32 = 0x40000000;
+const TILED_FLIP_DIAGONAL: u32 = 0x20000000;
+const TILED_GID_MASK: u32 = 0x0fffffff;
+
+// Per-cell flip flags written to the map file. Must match tilemap.rs
+const FLIP_HORIZONTAL: u8 = 1;
+const FLIP_VERTICAL: u8 = 2;
+const FLIP_DIAGONAL: u8 = 4;
+
+#[derive(Debug)]
+struct LayerInfo {
+    name: String,
+    flags: u32,
+    parallax_x: f32,
+    parallax_y: f32,
+    tile_data: Vec<u16>,
+    flip_data: Vec<u8>,
+}
+
+// A sequence of (tile index, duration in milliseconds)
+type TileAnimation = Vec<(usize, u32)>;
+
+#[derive(Debug)]
+struct TilesetInfo {
+    image_paths: Vec<String>,
+    tile_flags: Vec<u8>,
+
+    // (tile index, frames). Indices are relative to the start of the tileset.
+    animations: Vec<(usize, TileAnimation)>,
+}
+
+// Custom property types. These must match tilemap.rs
+const PROPERTY_BOOL: u8 = 0;
+const PROPERTY_INT: u8 = 1;
+const PROPERTY_FLOAT: u8 = 2;
+const PROPERTY_STRING: u8 = 3;
+
+#[derive(Debug)]
+enum PropertyValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+#[derive(Debug)]
+struct ObjectInfo {
+    type_name: String,
+    name: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    properties: Vec<(String, PropertyValue)>,
+}
+
 #[derive(Debug)]
 struct TileMapInfo {
     source_path: String,
     width: i32,
     height: i32,
-    tile_data: Vec<u8>,
+    layers: Vec<LayerInfo>,
     image_paths: Vec<String>,
     tile_flags: Vec<u8>,
-    objects: Vec<(String, i32, i32)>,
+    animations: Vec<(usize, TileAnimation)>,
+    objects: Vec<ObjectInfo>,
     player_start_x: i32,
     player_start_y: i32,
 }
@@ -63,14 +145,24 @@ fn main() {
     println!("cargo::rerun-if-changed=assets/");
     println!("cargo::rerun-if-changed=build.rs");
 
+    // Lets tests find the generated files without knowing cargo's layout.
+    println!("cargo::rustc-env=ASSET_DIR={}", target_dir);
+
     let sprite_ids = read_sprite_list("assets/sprites.txt");
 
     let mut image_paths: HashSet<String> = HashSet::new();
-    let tile_map = read_tmx_file("assets/map.tmx");
-
-    println!("{:?}", tile_map);
+    let tile_maps = read_maps("assets/maps");
+    for tile_map in &tile_maps {
+        println!("{:?}", tile_map);
+        image_paths.extend(
+            tile_map
+                .image_paths
+                .iter()
+                .filter(|path| !path.is_empty())
+                .cloned(),
+        );
+    }
 
-    image_paths.extend(tile_map.image_paths.iter().cloned());
     image_paths.extend(sprite_ids.iter().map(|(_, path, _, _)| path.clone()));
 
     println!("All images {:?}", image_paths);
@@ -103,7 +195,9 @@ fn main() {
         panic!("{}", msg);
     }
 
-    write_tile_map_file(&target_dir, &tile_map, &image_coordinates);
+    for tile_map in &tile_maps {
+        write_tile_map_file(&target_dir, tile_map, &image_coordinates);
+    }
 
     let audio_define_path = format!("{}/sounds.rs", build_dir);
     copy_sound_effects("assets/sound-effects.txt", &audio_define_path, &target_dir);
@@ -301,21 +395,31 @@ fn write_sprite_locations(
 //    num_tiles: u32
 //    tile_locs: [(f32, f32, f32, f32); num_tiles]
 //    tile_flags: [u8; num_tiles]
-//    map: [u8; width * height]
+//    num_animations: u32
+//    animations: [tile_index: u32, num_frames: u32,
+//                 frames: [(tile_index: u32, duration_ms: u32); num_frames]]
+//    num_layers: u32
+//    layers: [name: [u8; 32], flags: u32, parallax_x: f32, parallax_y: f32,
+//             map: [u16; width * height], flips: [u8; width * height]]
+//
+// Tile numbers in the map are 1 + the index into tile_locs/tile_flags, or
+// 0 for an empty cell. flips is a combination of the FLIP_* flags for each
+// cell.
 //    num_objects: u32
-//    objects: [name: [u8; 32], x: i32, y: i32]
+//    objects: [type: [u8; 32], name: [u8; 32], x: i32, y: i32, width: i32,
+//              height: i32, num_properties: u32,
+//              properties: [name: [u8; 32], type: u8, value; num_properties]]
+//
+// The property value depends on the type (one of the PROPERTY_* constants):
+// bool is a u8 (0 or 1), int is an i32, float is an f32, and string is
+// length: u32 followed by that many bytes of UTF-8.
 //
 fn write_tile_map_file(
     target_dir: &str,
     tile_map_info: &TileMapInfo,
     image_coordinates: &HashMap<String, AtlasLocation>,
 ) {
-    let output_file_name = Path::new(&tile_map_info.source_path)
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let dest_path = format!("{}/{}.bin", target_dir, output_file_name);
+    let dest_path = format!("{}/{}.bin", target_dir, get_map_name(tile_map_info));
     let output_file = fs::File::create(dest_path).unwrap();
     let mut writer = std::io::BufWriter::new(output_file);
     const MAGIC: &[u8; 4] = b"TMAP";
@@ -337,8 +441,12 @@ fn write_tile_map_file(
         .unwrap();
 
     for path in tile_map_info.image_paths.iter() {
-        assert!(image_coordinates.contains_key(path));
-        let (left, top, right, bottom, _width, _height) = image_coordinates.get(path).unwrap();
+        // Unused tile IDs (gaps between tilesets) have no image.
+        let (left, top, right, bottom, _width, _height) = if path.is_empty() {
+            &(0.0, 0.0, 0.0, 0.0, 0, 0)
+        } else {
+            image_coordinates.get(path).unwrap()
+        };
         println!(
             "Writing tile location for {}: {:?} {:?} {:?} {:?}",
             path, left, top, right, bottom
@@ -352,23 +460,93 @@ fn write_tile_map_file(
     writer
         .write_all(tile_map_info.tile_flags.as_slice())
         .unwrap();
+
     writer
-        .write_all(tile_map_info.tile_data.as_slice())
+        .write_all(&(tile_map_info.animations.len() as u32).to_le_bytes())
         .unwrap();
+    for (tile_index, frames) in &tile_map_info.animations {
+        writer
+            .write_all(&(*tile_index as u32).to_le_bytes())
+            .unwrap();
+        writer
+            .write_all(&(frames.len() as u32).to_le_bytes())
+            .unwrap();
+        for (frame_index, duration) in frames {
+            writer
+                .write_all(&(*frame_index as u32).to_le_bytes())
+                .unwrap();
+            writer.write_all(&duration.to_le_bytes()).unwrap();
+        }
+    }
 
-    let num_objects: u32 = tile_map_info.objects.len() as u32;
-    writer.write_all(&num_objects.to_le_bytes()).unwrap();
-    for (name, x, y) in &tile_map_info.objects {
+    writer
+        .write_all(&(tile_map_info.layers.len() as u32).to_le_bytes())
+        .unwrap();
+    for layer in &tile_map_info.layers {
         let mut name_temp = [0u8; 32];
-        name_temp[..name.len()].copy_from_slice(name.as_bytes());
+        name_temp[..layer.name.len()].copy_from_slice(layer.name.as_bytes());
         writer.write_all(&name_temp).unwrap();
-        writer.write_all(&x.to_le_bytes()).unwrap();
-        writer.write_all(&y.to_le_bytes()).unwrap();
+        writer.write_all(&layer.flags.to_le_bytes()).unwrap();
+        writer.write_all(&layer.parallax_x.to_le_bytes()).unwrap();
+        writer.write_all(&layer.parallax_y.to_le_bytes()).unwrap();
+        for tile in &layer.tile_data {
+            writer.write_all(&tile.to_le_bytes()).unwrap();
+        }
+
+        writer.write_all(layer.flip_data.as_slice()).unwrap();
+    }
+
+    let num_objects: u32 = tile_map_info.objects.len() as u32;
+    writer.write_all(&num_objects.to_le_bytes()).unwrap();
+    for object in &tile_map_info.objects {
+        write_name(&mut writer, &object.type_name);
+        write_name(&mut writer, &object.name);
+        writer.write_all(&object.x.to_le_bytes()).unwrap();
+        writer.write_all(&object.y.to_le_bytes()).unwrap();
+        writer.write_all(&object.width.to_le_bytes()).unwrap();
+        writer.write_all(&object.height.to_le_bytes()).unwrap();
+        writer
+            .write_all(&(object.properties.len() as u32).to_le_bytes())
+            .unwrap();
+        for (name, value) in &object.properties {
+            write_name(&mut writer, name);
+            match value {
+                PropertyValue::Bool(value) => {
+                    writer.write_all(&[PROPERTY_BOOL, *value as u8]).unwrap();
+                }
+                PropertyValue::Int(value) => {
+                    writer.write_all(&[PROPERTY_INT]).unwrap();
+                    writer.write_all(&value.to_le_bytes()).unwrap();
+                }
+                PropertyValue::Float(value) => {
+                    writer.write_all(&[PROPERTY_FLOAT]).unwrap();
+                    writer.write_all(&value.to_le_bytes()).unwrap();
+                }
+                PropertyValue::String(value) => {
+                    writer.write_all(&[PROPERTY_STRING]).unwrap();
+                    writer
+                        .write_all(&(value.len() as u32).to_le_bytes())
+                        .unwrap();
+                    writer.write_all(value.as_bytes()).unwrap();
+                }
+            }
+        }
     }
 
     writer.flush().unwrap();
 }
 
+// Write a zero padded, 32 byte string.
+fn write_name(writer: &mut impl Write, name: &str) {
+    if name.len() >= 32 {
+        panic!("Name {} is too long", name);
+    }
+
+    let mut name_temp = [0u8; 32];
+    name_temp[..name.len()].copy_from_slice(name.as_bytes());
+    writer.write_all(&name_temp).unwrap();
+}
+
 fn copy_sound_effects(manifest_path: &str, defines_path: &str, output_dir: &str) {
     let manifest = std::fs::read_to_string(manifest_path).unwrap();
     let files: Vec<(String, String)> = manifest
@@ -444,48 +622,86 @@ fn get_xml_attribute(attrs: &Attributes, name: &str) -> Option<String> {
     })
 }
 
-fn read_tileset(filename: &str) -> (Vec<String>, Vec<u8>) {
+fn read_tileset(filename: &str) -> TilesetInfo {
     let rawxml = std::fs::read_to_string(filename).unwrap();
     let mut reader = Reader::from_str(&rawxml);
     let mut buf = Vec::new();
     let mut current_tile_id = 0;
     let mut image_paths: Vec<String> = Vec::new();
     let mut tile_flags: Vec<u8> = Vec::new();
+    let mut animations: Vec<(usize, TileAnimation)> = Vec::new();
 
     loop {
         match reader.read_event_into(&mut buf) {
             Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
             Ok(Event::Eof) => break,
-            Ok(Event::Start(e)) => {
-                if e.name() == QName(b"tile") {
-                    current_tile_id = get_xml_attribute(&e.attributes(), "id")
+            Ok(Event::Start(e)) if e.name() == QName(b"tile") => {
+                current_tile_id = get_xml_attribute(&e.attributes(), "id")
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                while image_paths.len() <= current_tile_id {
+                    image_paths.push(String::new());
+                    tile_flags.push(0);
+                }
+            }
+            Ok(Event::Start(e)) if e.name() == QName(b"animation") => {
+                animations.push((current_tile_id, Vec::new()));
+            }
+            Ok(Event::Empty(e)) => match e.name() {
+                QName(b"frame") => {
+                    let frame_tile_id = get_xml_attribute(&e.attributes(), "tileid")
                         .unwrap()
                         .parse()
                         .unwrap();
-                    while image_paths.len() <= current_tile_id {
-                        image_paths.push(String::new());
-                        tile_flags.push(0);
-                    }
+                    let duration = get_xml_attribute(&e.attributes(), "duration")
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    animations
+                        .last_mut()
+                        .unwrap()
+                        .1
+                        .push((frame_tile_id, duration));
                 }
-            }
-            Ok(Event::Empty(e)) => match e.name() {
+
                 QName(b"image") => {
                     image_paths[current_tile_id] =
                         get_xml_attribute(&e.attributes(), "source").unwrap();
                 }
 
-                QName(b"property") => {
-                    if get_xml_attribute(&e.attributes(), "value").unwrap() == "true" {
-                        match get_xml_attribute(&e.attributes(), "name").unwrap().as_str() {
-                            "ladder" => {
-                                tile_flags[current_tile_id] |= 2;
-                            }
-                            "solid" => {
-                                tile_flags[current_tile_id] |= 1;
-                            }
-                            _ => {
-                                println!("unknown attribute");
-                            }
+                QName(b"property")
+                    if get_xml_attribute(&e.attributes(), "name").unwrap() == "slope" =>
+                {
+                    let value = get_xml_attribute(&e.attributes(), "value").unwrap();
+                    let index = SLOPE_NAMES
+                        .iter()
+                        .position(|&name| name == value)
+                        .unwrap_or_else(|| panic!("unknown slope type {}", value));
+                    tile_flags[current_tile_id] |= (index as u8 + 1) << SLOPE_SHIFT;
+                }
+
+                QName(b"property")
+                    if get_xml_attribute(&e.attributes(), "value").unwrap() == "true" =>
+                {
+                    match get_xml_attribute(&e.attributes(), "name").unwrap().as_str() {
+                        "ladder" => {
+                            tile_flags[current_tile_id] |= FLAG_LADDER;
+                        }
+                        "solid" => {
+                            tile_flags[current_tile_id] |= FLAG_SOLID;
+                        }
+                        "water" => {
+                            tile_flags[current_tile_id] |= FLAG_WATER;
+                        }
+                        "one_way" => {
+                            tile_flags[current_tile_id] |= FLAG_ONE_WAY;
+                        }
+                        "breakable" => {
+                            tile_flags[current_tile_id] |= FLAG_BREAKABLE;
+                        }
+                        _ => {
+                            println!("unknown attribute");
                         }
                     }
                 }
@@ -496,17 +712,155 @@ fn read_tileset(filename: &str) -> (Vec<String>, Vec<u8>) {
         }
     }
 
-    (image_paths, tile_flags)
+    TilesetInfo {
+        image_paths,
+        tile_flags,
+        animations,
+    }
+}
+
+fn read_object(attrs: &Attributes) -> ObjectInfo {
+    let get_coord = |name| {
+        get_xml_attribute(attrs, name)
+            .map(|value| value.parse::<f32>().unwrap() as i32)
+            .unwrap_or(0)
+    };
+
+    // Newer versions of Tiled call type "class"
+    let type_name = get_xml_attribute(attrs, "type")
+        .or_else(|| get_xml_attribute(attrs, "class"))
+        .unwrap_or_default();
+    ObjectInfo {
+        type_name,
+        name: get_xml_attribute(attrs, "name").unwrap_or_default(),
+        x: get_coord("x"),
+        y: get_coord("y"),
+        width: get_coord("width"),
+        height: get_coord("height"),
+        properties: Vec::new(),
+    }
+}
+
+fn add_object(
+    object: ObjectInfo,
+    objects: &mut Vec<ObjectInfo>,
+    player_start_x: &mut i32,
+    player_start_y: &mut i32,
+) {
+    // This is a special object that indicates the player's start location.
+    // Spawn points (where warps from other maps arrive) are snapped to tiles
+    // the same way.
+    if object.type_name == "Player" {
+        *player_start_x = ((object.x + 32) / 64) * 64;
+        *player_start_y = ((object.y + 32) / 64) * 64;
+    } else if object.type_name == "Spawn" {
+        objects.push(ObjectInfo {
+            x: ((object.x + 32) / 64) * 64,
+            y: ((object.y + 32) / 64) * 64,
+            ..object
+        });
+    } else {
+        objects.push(object);
+    }
+}
+
+// Tiled stores the property type as an attribute (string if not specified).
+// Colors, files, and object references are exported as their string value.
+fn read_property_value(attrs: &Attributes) -> PropertyValue {
+    let value = get_xml_attribute(attrs, "value").unwrap_or_default();
+    let property_type = get_xml_attribute(attrs, "type").unwrap_or_default();
+    match property_type.as_str() {
+        "bool" => PropertyValue::Bool(value == "true"),
+        "int" => PropertyValue::Int(value.parse().unwrap()),
+        "float" => PropertyValue::Float(value.parse().unwrap()),
+        _ => PropertyValue::String(value),
+    }
+}
+
+// Read every .tmx file in the directory. Each is written to a .bin file
+// with the same name in the target directory.
+fn read_maps(dir: &str) -> Vec<TileMapInfo> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tmx"))
+        .collect();
+
+    // Sorted so the build output is the same every time.
+    paths.sort();
+    let tile_maps: Vec<TileMapInfo> = paths
+        .iter()
+        .map(|path| read_tmx_file(path.to_str().unwrap()))
+        .collect();
+    check_warps(&tile_maps);
+    tile_maps
+}
+
+fn get_map_name(tile_map: &TileMapInfo) -> &str {
+    Path::new(&tile_map.source_path)
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap()
+}
+
+// Make sure every warp leads somewhere, so a typo fails the build rather than
+// crashing the game when the player walks into it. The spawn property may be
+// empty, which means the destination map's player start.
+fn check_warps(tile_maps: &[TileMapInfo]) {
+    let get_string_property = |object: &ObjectInfo, name: &str| {
+        object
+            .properties
+            .iter()
+            .find(|(property_name, _)| property_name == name)
+            .map(|(_, value)| match value {
+                PropertyValue::String(value) => value.clone(),
+                _ => panic!("warp {} property {} is not a string", object.name, name),
+            })
+            .unwrap_or_else(|| panic!("warp {} has no {} property", object.name, name))
+    };
+
+    for tile_map in tile_maps {
+        for warp in tile_map.objects.iter().filter(|o| o.type_name == "Warp") {
+            let dest_name = get_string_property(warp, "map");
+            let spawn_name = get_string_property(warp, "spawn");
+            let dest_map = tile_maps
+                .iter()
+                .find(|map| get_map_name(map) == dest_name)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{}: warp {} goes to unknown map {}",
+                        tile_map.source_path, warp.name, dest_name
+                    )
+                });
+
+            let has_spawn = spawn_name.is_empty()
+                || dest_map
+                    .objects
+                    .iter()
+                    .any(|object| object.type_name == "Spawn" && object.name == spawn_name);
+            if !has_spawn {
+                panic!(
+                    "{}: warp {} goes to unknown spawn point {} in map {}",
+                    tile_map.source_path, warp.name, spawn_name, dest_name
+                );
+            }
+        }
+    }
 }
 
 fn read_tmx_file(filename: &str) -> TileMapInfo {
     let rawxml = std::fs::read_to_string(filename).unwrap();
     let mut reader = Reader::from_str(&rawxml);
     let mut buf = Vec::new();
-    let mut tile_data: Vec<u8> = Vec::new();
+    let mut layers: Vec<LayerInfo> = Vec::new();
+    let mut in_layer = false;
+    let mut in_data = false;
     let mut image_paths: Vec<String> = Vec::new();
     let mut tile_flags: Vec<u8> = Vec::new();
-    let mut objects: Vec<(String, i32, i32)> = Vec::new();
+    let mut animations: Vec<(usize, TileAnimation)> = Vec::new();
+    let mut objects: Vec<ObjectInfo> = Vec::new();
+    let mut current_object: Option<ObjectInfo> = None;
     let mut width: i32 = 0;
     let mut height: i32 = 0;
     let mut player_start_x: i32 = 0;
@@ -516,76 +870,247 @@ fn read_tmx_file(filename: &str) -> TileMapInfo {
         match reader.read_event_into(&mut buf) {
             Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
             Ok(Event::Eof) => break,
-            Ok(Event::Start(e)) => {
-                if let QName(b"layer") = e.name() {
-                    width = get_xml_attribute(&e.attributes(), "width")
+            Ok(Event::Start(e)) => match e.name() {
+                QName(b"layer") => {
+                    let layer_width: i32 = get_xml_attribute(&e.attributes(), "width")
                         .unwrap()
                         .parse()
                         .unwrap();
-                    height = get_xml_attribute(&e.attributes(), "height")
+                    let layer_height: i32 = get_xml_attribute(&e.attributes(), "height")
                         .unwrap()
                         .parse()
                         .unwrap();
-                    println!("layer {}x{}", width, height);
+                    let name = get_xml_attribute(&e.attributes(), "name").unwrap_or_default();
+                    println!("layer {} {}x{}", name, layer_width, layer_height);
+                    if name.len() >= 32 {
+                        panic!("Layer name {} is too long", name);
+                    }
+
+                    // The map file has one size for all layers.
+                    if layers.is_empty() {
+                        width = layer_width;
+                        height = layer_height;
+                    } else if layer_width != width || layer_height != height {
+                        panic!(
+                            "{}: layer {} is {}x{}, but the first layer is {}x{}",
+                            filename, name, layer_width, layer_height, width, height
+                        );
+                    }
+
+                    // Tiled omits the parallax factors if they are 1.
+                    let get_parallax = |attr_name| {
+                        get_xml_attribute(&e.attributes(), attr_name)
+                            .map(|value| value.parse().unwrap())
+                            .unwrap_or(1.0)
+                    };
+
+                    layers.push(LayerInfo {
+                        name,
+                        flags: 0,
+                        parallax_x: get_parallax("parallaxx"),
+                        parallax_y: get_parallax("parallaxy"),
+                        tile_data: Vec::new(),
+                        flip_data: Vec::new(),
+                    });
+                    in_layer = true;
                 }
-            }
+                QName(b"data") => in_data = true,
+
+                // A tileset that refers to a .tsx file is an empty element
+                // (handled below). One with child elements is embedded in
+                // the map.
+                QName(b"tileset") => panic!(
+                    "{}: embedded tilesets are not supported, export the tileset to a .tsx file",
+                    filename
+                ),
+
+                // An object with properties. These are added before the end
+                // tag.
+                QName(b"object") => current_object = Some(read_object(&e.attributes())),
+                _ => (),
+            },
+            Ok(Event::End(e)) => match e.name() {
+                QName(b"layer") => in_layer = false,
+                QName(b"data") => in_data = false,
+                QName(b"object") => {
+                    let object = current_object.take().unwrap();
+                    add_object(
+                        object,
+                        &mut objects,
+                        &mut player_start_x,
+                        &mut player_start_y,
+                    );
+                }
+                _ => (),
+            },
             Ok(Event::Empty(e)) => match e.name() {
+                QName(b"property") if current_object.is_some() => {
+                    let name = get_xml_attribute(&e.attributes(), "name").unwrap();
+                    let value = read_property_value(&e.attributes());
+                    current_object
+                        .as_mut()
+                        .unwrap()
+                        .properties
+                        .push((name, value));
+                }
+
+                QName(b"property")
+                    if in_layer
+                        && get_xml_attribute(&e.attributes(), "value").unwrap() == "true" =>
+                {
+                    let layer = layers.last_mut().unwrap();
+                    match get_xml_attribute(&e.attributes(), "name").unwrap().as_str() {
+                        "collision" => {
+                            layer.flags |= LAYER_COLLISION;
+                        }
+                        "foreground" => {
+                            layer.flags |= LAYER_FOREGROUND;
+                        }
+                        _ => {
+                            println!("unknown layer attribute");
+                        }
+                    }
+                }
+
                 QName(b"tileset") => {
-                    let first_gid: u32 = get_xml_attribute(&e.attributes(), "firstgid")
+                    // The tile IDs in the layer data (GIDs) are global to the
+                    // map. Each tileset's tiles are numbered from its
+                    // firstgid. Since tile numbers in the output file are
+                    // index + 1, the tileset's tiles go at index firstgid - 1.
+                    let first_gid: usize = get_xml_attribute(&e.attributes(), "firstgid")
                         .unwrap()
                         .parse()
                         .unwrap();
-                    if first_gid != 1 {
-                        panic!("first_gid != 1");
+                    if first_gid - 1 < image_paths.len() {
+                        panic!("tileset firstgid {} overlaps previous tileset", first_gid);
                     }
 
-                    let tsx_file = get_xml_attribute(&e.attributes(), "source").unwrap();
-                    (image_paths, tile_flags) = read_tileset(&format!("assets/{}", tsx_file));
+                    let tsx_file =
+                        get_xml_attribute(&e.attributes(), "source").unwrap_or_else(|| {
+                            panic!("{}: embedded tilesets are not supported", filename)
+                        });
+                    let tsx_path = Path::new(filename).parent().unwrap().join(tsx_file);
+                    let tileset = read_tileset(tsx_path.to_str().unwrap());
+                    let base_index = first_gid - 1;
+                    image_paths.resize(base_index, String::new());
+                    tile_flags.resize(base_index, 0);
+                    image_paths.extend(tileset.image_paths);
+                    tile_flags.extend(tileset.tile_flags);
+                    animations.extend(tileset.animations.into_iter().map(|(index, frames)| {
+                        (
+                            base_index + index,
+                            frames
+                                .into_iter()
+                                .map(|(frame_index, duration)| (base_index + frame_index, duration))
+                                .collect(),
+                        )
+                    }));
                 }
 
                 QName(b"object") => {
-                    let x_loc: f32 = get_xml_attribute(&e.attributes(), "x")
-                        .unwrap()
-                        .parse()
-                        .unwrap();
-                    let y_loc: f32 = get_xml_attribute(&e.attributes(), "y")
-                        .unwrap()
-                        .parse()
-                        .unwrap();
-                    let objtype = get_xml_attribute(&e.attributes(), "type").unwrap();
-
-                    // This is a special object that indicates the player's start location.
-                    if objtype == "Player" {
-                        player_start_x = ((x_loc as i32 + 32) / 64) * 64;
-                        player_start_y = ((y_loc as i32 + 32) / 64) * 64;
-                    } else {
-                        objects.push((objtype.clone(), x_loc as i32, y_loc as i32));
-                    }
+                    let object = read_object(&e.attributes());
+                    add_object(
+                        object,
+                        &mut objects,
+                        &mut player_start_x,
+                        &mut player_start_y,
+                    );
                 }
 
                 _ => (),
             },
-            Ok(Event::Text(e)) => {
-                tile_data.extend(
-                    e.unescape()
-                        .unwrap()
-                        .split(',')
-                        .map(|elem| elem.trim())
-                        .filter(|tok| !tok.is_empty())
-                        .map(|tok| tok.parse::<u8>().expect("")),
-                );
+            Ok(Event::Text(e)) if in_data => {
+                let layer = layers.last_mut().unwrap();
+                for tok in e
+                    .unescape()
+                    .unwrap()
+                    .split(',')
+                    .map(|elem| elem.trim())
+                    .filter(|tok| !tok.is_empty())
+                {
+                    let gid: u32 = tok
+                        .parse()
+                        .unwrap_or_else(|_| panic!("invalid tile ID {}", tok));
+                    let tile_id = gid & TILED_GID_MASK;
+                    if tile_id > u16::MAX as u32 {
+                        panic!("tile ID {} out of range", tile_id);
+                    }
+
+                    let mut flips = 0;
+                    if (gid & TILED_FLIP_HORIZONTAL) != 0 {
+                        flips |= FLIP_HORIZONTAL;
+                    }
+
+                    if (gid & TILED_FLIP_VERTICAL) != 0 {
+                        flips |= FLIP_VERTICAL;
+                    }
+
+                    if (gid & TILED_FLIP_DIAGONAL) != 0 {
+                        flips |= FLIP_DIAGONAL;
+                    }
+
+                    layer.tile_data.push(tile_id as u16);
+                    layer.flip_data.push(flips);
+                }
             }
             _ => (),
         }
     }
 
+    let num_collision_layers = layers
+        .iter()
+        .filter(|layer| layer.flags & LAYER_COLLISION != 0)
+        .count();
+    if num_collision_layers != 1 {
+        panic!(
+            "{}: exactly one layer must have the collision property (found {})",
+            filename, num_collision_layers
+        );
+    }
+
+    if image_paths.len() > u16::MAX as usize {
+        panic!("{}: too many tiles ({})", filename, image_paths.len());
+    }
+
+    // TileMap::new reads width * height cells per layer, and looks up the
+    // flags for every tile, so either being wrong would break the game
+    // rather than the build.
+    for layer in &layers {
+        if layer.tile_data.len() != (width * height) as usize {
+            panic!(
+                "{}: layer {} has {} tiles, expected {}x{}",
+                filename,
+                layer.name,
+                layer.tile_data.len(),
+                width,
+                height
+            );
+        }
+
+        // Tile numbers are index + 1 (0 is empty).
+        if let Some(&tile_id) = layer
+            .tile_data
+            .iter()
+            .find(|&&tile_id| tile_id as usize > image_paths.len())
+        {
+            panic!(
+                "{}: layer {} uses tile {}, but the tilesets only have {} tiles",
+                filename,
+                layer.name,
+                tile_id,
+                image_paths.len()
+            );
+        }
+    }
+
     TileMapInfo {
         source_path: filename.to_string(),
         width,
         height,
-        tile_data,
+        layers,
         image_paths,
         tile_flags,
+        animations,
         objects,
         player_start_x,
         player_start_y,
@@ -17,10 +17,38 @@
 extern crate sdl2;
 
 use sdl2::mixer;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 static mut EFFECTS: Vec<mixer::Chunk> = Vec::new();
 
+// When set, no audio device is opened. Effects are recorded rather than
+// played so tests can check them. Like the rest of the audio state, this is
+// shared by the whole process rather than belonging to an engine, so null
+// audio is only meant for tests and other headless runs. It stays in effect
+// until init_audio is called.
+static NULL_AUDIO: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static PLAYED_EFFECTS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn init_null_audio() {
+    NULL_AUDIO.store(true, Ordering::Relaxed);
+}
+
+fn is_null_audio() -> bool {
+    NULL_AUDIO.load(Ordering::Relaxed)
+}
+
+// Return the effects played on this thread since the last call (null
+// audio only).
+pub fn take_played_effects() -> Vec<usize> {
+    PLAYED_EFFECTS.with(|played| std::mem::take(&mut *played.borrow_mut()))
+}
+
 pub fn init_audio(audio_file_list: &[&str]) {
+    NULL_AUDIO.store(false, Ordering::Relaxed);
     mixer::init(mixer::InitFlag::MP3).unwrap();
     mixer::open_audio(44100, mixer::AUDIO_S16LSB, mixer::DEFAULT_CHANNELS, 1024).unwrap();
     mixer::allocate_channels(4);
@@ -38,24 +66,37 @@ pub fn init_audio(audio_file_list: &[&str]) {
 }
 
 pub fn play_effect(num: usize) {
+    if is_null_audio() {
+        PLAYED_EFFECTS.with(|played| played.borrow_mut().push(num));
+        return;
+    }
+
     let fx = unsafe { &EFFECTS[num] };
     sdl2::mixer::Channel::all().play(fx, 0).unwrap();
 }
 
-pub fn play_music(file_name: &str) -> sdl2::mixer::Music<'_>  {
+pub fn play_music(file_name: &str) -> Option<sdl2::mixer::Music<'static>> {
+    if is_null_audio() {
+        return None;
+    }
+
     let exe_path = std::env::current_exe().unwrap();
     let exe_dir = exe_path.parent().unwrap();
     let music_path = exe_dir.join(file_name);
 
     let music = sdl2::mixer::Music::from_file(&music_path).unwrap();
     music.play(-1).unwrap();
-    music
+    Some(music)
 }
 
 pub fn pause_music() {
-    sdl2::mixer::Music::pause();
+    if !is_null_audio() {
+        sdl2::mixer::Music::pause();
+    }
 }
 
 pub fn resume_music() {
-    sdl2::mixer::Music::resume();
+    if !is_null_audio() {
+        sdl2::mixer::Music::resume();
+    }
 }
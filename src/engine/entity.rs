@@ -15,9 +15,11 @@
 //
 
 use crate::gfx;
+use crate::input;
 use crate::tilemap;
 use crate::util;
 use std::any::Any;
+use std::collections::HashMap;
 
 pub const CONTROL_UP: u32 = 0x1;
 pub const CONTROL_DOWN: u32 = 0x2;
@@ -27,16 +29,57 @@ pub const CONTROL_FIRE: u32 = 0x10;
 pub const CONTROL_JUMP: u32 = 0x20;
 pub const CONTROL_MENU: u32 = 0x40;
 
+// What an entity can see and change while updating. Entities and tile
+// changes (see TileMap::set_tile) added to new_entities and tile_edits are
+// applied after all entities have been updated.
+pub struct UpdateContext<'a> {
+    pub d_t: f32,
+    pub new_entities: &'a mut Vec<Box<dyn Entity>>,
+    pub tile_edits: &'a mut Vec<tilemap::TileEdit>,
+    pub input: &'a input::InputState,
+    pub tile_map: &'a tilemap::TileMap,
+    pub world: &'a EntitySnapshot,
+    pub rng: &'a mut util::GameRng,
+}
+
 pub trait Entity: Any {
-    fn update(
-        &mut self,
-        d_t: f32,
-        new_entities: &mut Vec<Box<dyn Entity>>,
-        buttons: u32,
-        tile_map: &tilemap::TileMap,
-        player_rect: &util::Rect<i32>,
-    );
-    fn draw(&self, context: &mut gfx::RenderContext);
+    fn update(&mut self, ctx: &mut UpdateContext);
+
+    // Called when the entity is added to the engine, with the ID that other
+    // entities can use to refer to it.
+    fn on_spawn(&mut self, _id: EntityId) {}
+
+    // Called after tiles in the map have changed. region is the area that
+    // changed, in tiles.
+    fn tiles_changed(&mut self, _region: &util::Rect<i32>) {}
+
+    // Called when the player is carried into another map (see
+    // GameEngine::change_map). x and y are the location of the spawn point,
+    // in the same form as the player start.
+    fn move_to(&mut self, _x: f32, _y: f32) {}
+
+    // Entities created for map objects are removed when they get far from
+    // the camera and created again from the object when it comes back (see
+    // spawner.rs). Whatever this returns is passed to restore_state on the
+    // new entity, so it can carry on where it left off rather than starting
+    // over. It is also kept if the player leaves the map and comes back.
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    fn restore_state(&mut self, _state: Box<dyn Any>) {}
+
+    // Transient entities, such as projectiles, are thrown away once they get
+    // far from the camera. Other entities that weren't created from map
+    // objects are kept until they die.
+    fn is_transient(&self) -> bool {
+        false
+    }
+
+    // alpha is the fraction of a time step (0.0-1.0) since the last update.
+    // Entities should draw themselves at a position interpolated between
+    // where they were before and after that update so motion is smooth.
+    fn draw(&self, context: &mut gfx::RenderContext, alpha: f32);
     fn is_live(&self) -> bool;
 
     // Each bit in this represents a type of entity, which is used
@@ -50,31 +93,589 @@ pub trait Entity: Any {
     // Return axis aligned bounding box of this object (left, top, width, height)
     // If two entities bounding boxes overlap they are considered to collide.
     fn get_bounding_box(&self) -> util::Rect<i32>;
-    fn collide(&mut self, other: &dyn Entity);
+
+    // Used to decide whether an entity landed on another (see
+    // Contact::landed). Positive is down.
+    fn get_vertical_velocity(&self) -> f32 {
+        0.0
+    }
+
+    fn collide(&mut self, contact: &Contact);
 
     fn as_any(&self) -> &dyn Any;
 }
 
-// Check for objects overlapping and call their collision handlers
-// This is a brute force O(n^2) algorithm. While a broad phase step
-// would reduce the computational complexity, for fairly small
-// numbers of objects this is probably preferable.
-pub fn handle_collisions(entities: &mut Vec<Box<dyn Entity>>) {
-    for i in 0..entities.len() - 1 {
-        let (arr1, arr2) = entities.split_at_mut(i + 1);
+// Refers to an entity without borrowing it. Once an entity is removed, IDs
+// that referred to it are stale: lookups will fail rather than finding
+// whatever entity reuses its storage, because the generation is
+// incremented each time a slot is freed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct EntityId {
+    slot: u32,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+
+    // Position of the entity in EntityList::entities, or None if the slot
+    // is free.
+    position: Option<usize>,
+}
+
+// Entities are stored contiguously, in the order they were spawned, so they
+// can be iterated quickly. A separate slot table maps IDs to positions.
+#[derive(Default)]
+pub struct EntityList {
+    entities: Vec<Box<dyn Entity>>,
+    ids: Vec<EntityId>,
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+}
+
+impl EntityList {
+    pub fn add(&mut self, mut entity: Box<dyn Entity>) -> EntityId {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot::default());
+                (self.slots.len() - 1) as u32
+            }
+        };
+
+        let id = EntityId {
+            slot,
+            generation: self.slots[slot as usize].generation,
+        };
+        self.slots[slot as usize].position = Some(self.entities.len());
+        entity.on_spawn(id);
+        self.entities.push(entity);
+        self.ids.push(id);
+        id
+    }
+
+    fn get_position(&self, id: EntityId) -> Option<usize> {
+        let slot = self.slots.get(id.slot as usize)?;
+        if slot.generation == id.generation {
+            slot.position
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&dyn Entity> {
+        self.get_position(id)
+            .map(|position| self.entities[position].as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut Box<dyn Entity>> {
+        self.get_position(id)
+            .map(|position| &mut self.entities[position])
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.get_position(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Box<dyn Entity>> {
+        self.entities.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Box<dyn Entity>> {
+        self.entities.iter_mut()
+    }
+
+    // IDs, in the same order as iter.
+    pub fn ids(&self) -> &[EntityId] {
+        &self.ids
+    }
+
+    // Take an entity out of the list (for example, to add it to another
+    // one). Other entities keep their order.
+    pub fn remove(&mut self, id: EntityId) -> Option<Box<dyn Entity>> {
+        let position = self.get_position(id)?;
+        let slot = &mut self.slots[id.slot as usize];
+        slot.position = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(id.slot);
+        self.ids.remove(position);
+        for later_id in &self.ids[position..] {
+            self.slots[later_id.slot as usize].position = self.slots[later_id.slot as usize]
+                .position
+                .map(|later_position| later_position - 1);
+        }
+
+        Some(self.entities.remove(position))
+    }
+
+    // Remove all entities where is_live returns false.
+    pub fn remove_dead(&mut self) {
+        let mut dest = 0;
+        for src in 0..self.entities.len() {
+            let slot = &mut self.slots[self.ids[src].slot as usize];
+            if self.entities[src].is_live() {
+                slot.position = Some(dest);
+                self.entities.swap(dest, src);
+                self.ids.swap(dest, src);
+                dest += 1;
+            } else {
+                slot.position = None;
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free_slots.push(self.ids[src].slot);
+            }
+        }
+
+        self.entities.truncate(dest);
+        self.ids.truncate(dest);
+    }
+
+    pub fn clear(&mut self) {
+        for id in &self.ids {
+            let slot = &mut self.slots[id.slot as usize];
+            slot.position = None;
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free_slots.push(id.slot);
+        }
+
+        self.entities.clear();
+        self.ids.clear();
+    }
+
+    pub fn snapshot(&self, player_id: Option<EntityId>) -> EntitySnapshot {
+        EntitySnapshot {
+            bounding_boxes: self
+                .ids
+                .iter()
+                .zip(self.entities.iter())
+                .map(|(id, entity)| (*id, entity.get_bounding_box()))
+                .collect(),
+            player_id,
+        }
+    }
+}
+
+// A read only view of where entities were at the start of the simulation
+// step. Passed to Entity::update so entities can track each other (while
+// they are all being mutated).
+#[derive(Default)]
+pub struct EntitySnapshot {
+    bounding_boxes: HashMap<EntityId, util::Rect<i32>>,
+    player_id: Option<EntityId>,
+}
+
+impl EntitySnapshot {
+    pub fn player_id(&self) -> Option<EntityId> {
+        self.player_id
+    }
+
+    pub fn player_rect(&self) -> Option<util::Rect<i32>> {
+        self.get_bounding_box(self.player_id?)
+    }
+
+    // Returns None if the entity has been removed.
+    pub fn get_bounding_box(&self, id: EntityId) -> Option<util::Rect<i32>> {
+        self.bounding_boxes.get(&id).copied()
+    }
+}
+
+// Describes a collision, from the point of view of the entity whose collide
+// method is being called.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub other_id: EntityId,
+    pub other_class: u32,
+
+    // Intersection of the two bounding boxes.
+    pub overlap: util::Rect<i32>,
+
+    // Minimum translation vector: the shortest distance (x, y) this entity
+    // would need to move to no longer overlap the other one. It is along
+    // whichever axis has the smaller penetration, so, for example, a
+    // negative y means this entity hit the other from above.
+    pub mtv: (i32, i32),
+
+    // The upper entity landed on the lower one: they hit vertically (see
+    // mtv) and the upper one wasn't moving up. This is decided once for
+    // both entities, so they always agree (e.g. whether a stomp happened).
+    pub landed: bool,
+}
+
+// Build the contact for rect1 hitting rect2. The contact for the other
+// entity is the same, but with the MTV negated.
+fn make_contact(
+    rect1: &util::Rect<i32>,
+    rect2: &util::Rect<i32>,
+    other_id: EntityId,
+    other_class: u32,
+) -> Contact {
+    let left = rect1.left.max(rect2.left);
+    let top = rect1.top.max(rect2.top);
+    let overlap = util::Rect::<i32>::new(
+        left,
+        top,
+        rect1.right().min(rect2.right()) - left,
+        rect1.bottom().min(rect2.bottom()) - top,
+    );
+
+    // Compare centers (doubled to avoid rounding).
+    let mtv = if overlap.width < overlap.height {
+        if rect1.left * 2 + rect1.width < rect2.left * 2 + rect2.width {
+            (-overlap.width, 0)
+        } else {
+            (overlap.width, 0)
+        }
+    } else if rect1.top * 2 + rect1.height < rect2.top * 2 + rect2.height {
+        (0, -overlap.height)
+    } else {
+        (0, overlap.height)
+    };
+
+    Contact {
+        other_id,
+        other_class,
+        overlap,
+        mtv,
+        landed: false,
+    }
+}
+
+// Check for objects overlapping and call their collision handlers.
+// Rather than testing every pair of entities, this first buckets them into
+// a grid (the broad phase), then only tests pairs that share a cell.
+// Handlers are called in the same order as testing every pair would:
+// ordered by the index of the first entity, then the second.
+pub fn handle_collisions(entities: &mut EntityList) {
+    let boxes: Vec<util::Rect<i32>> = entities
+        .iter()
+        .map(|entity| entity.get_bounding_box())
+        .collect();
+
+    for (i, j) in find_candidate_pairs(&boxes) {
+        let (arr1, arr2) = entities.entities.split_at_mut(j);
         let e1 = &mut arr1[i];
-        let box1 = e1.get_bounding_box();
-        for e2 in arr2.iter_mut() {
-            let box2 = e2.get_bounding_box();
-            if box1.overlaps(&box2) {
-                if (e1.get_collision_mask() & e2.get_collision_class()) != 0 {
-                    e1.collide(e2.as_ref());
-                }
+        let e2 = &mut arr2[0];
+
+        // Re-fetch the boxes, since they may have been changed by an earlier
+        // collision handler.
+        let rect1 = e1.get_bounding_box();
+        let rect2 = e2.get_bounding_box();
+        if rect1.overlaps(&rect2) {
+            let class1 = e1.get_collision_class();
+            let class2 = e2.get_collision_class();
+            let mut contact = make_contact(&rect1, &rect2, entities.ids[j], class2);
+            contact.landed = match contact.mtv.1.cmp(&0) {
+                std::cmp::Ordering::Less => e1.get_vertical_velocity() >= 0.0,
+                std::cmp::Ordering::Greater => e2.get_vertical_velocity() >= 0.0,
+                std::cmp::Ordering::Equal => false,
+            };
+            if (e1.get_collision_mask() & class2) != 0 {
+                e1.collide(&contact);
+            }
+
+            if (e2.get_collision_mask() & class1) != 0 {
+                e2.collide(&Contact {
+                    other_id: entities.ids[i],
+                    other_class: class1,
+                    mtv: (-contact.mtv.0, -contact.mtv.1),
+                    ..contact
+                });
+            }
+        }
+    }
+}
+
+// Cells are the same size as tiles, which is a bit larger than most
+// entities, so each one usually only touches a few cells.
+const CELL_SIZE: i32 = tilemap::TILE_SIZE;
 
-                if (e2.get_collision_mask() & e1.get_collision_class()) != 0 {
-                    e2.collide(e1.as_ref());
+// Return the sorted list of all index pairs (i < j) whose bounding boxes
+// are in at least one common grid cell. This is a superset of the pairs that
+// overlap.
+fn find_candidate_pairs(boxes: &[util::Rect<i32>]) -> Vec<(usize, usize)> {
+    let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (index, bounds) in boxes.iter().enumerate() {
+        let left_cell = bounds.left.div_euclid(CELL_SIZE);
+        let right_cell = (bounds.right() - 1).max(bounds.left).div_euclid(CELL_SIZE);
+        let top_cell = bounds.top.div_euclid(CELL_SIZE);
+        let bottom_cell = (bounds.bottom() - 1).max(bounds.top).div_euclid(CELL_SIZE);
+        for y in top_cell..=bottom_cell {
+            for x in left_cell..=right_cell {
+                grid.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+
+    // Indices within each cell are in ascending order because they were
+    // inserted that way.
+    let mut pairs = Vec::new();
+    for cell in grid.values() {
+        for (n, &i) in cell.iter().enumerate() {
+            for &j in &cell[n + 1..] {
+                pairs.push((i, j));
+            }
+        }
+    }
+
+    // Entities that span multiple cells will be paired more than once.
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    struct TestEntity {
+        tag: i32,
+        live: bool,
+        bounds: util::Rect<i32>,
+        contacts: Vec<Contact>,
+    }
+
+    impl Entity for TestEntity {
+        fn update(&mut self, _ctx: &mut UpdateContext) {}
+
+        fn draw(&self, _context: &mut gfx::RenderContext, _alpha: f32) {}
+
+        fn is_live(&self) -> bool {
+            self.live
+        }
+
+        fn get_collision_class(&self) -> u32 {
+            1
+        }
+
+        fn get_collision_mask(&self) -> u32 {
+            1
+        }
+
+        fn get_bounding_box(&self) -> util::Rect<i32> {
+            self.bounds
+        }
+
+        fn collide(&mut self, contact: &Contact) {
+            self.contacts.push(*contact);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn add_test_entity(list: &mut EntityList, tag: i32) -> EntityId {
+        add_test_entity_at(list, tag, util::Rect::<i32>::new(tag * 10, 0, 1, 1))
+    }
+
+    fn add_test_entity_at(list: &mut EntityList, tag: i32, bounds: util::Rect<i32>) -> EntityId {
+        list.add(Box::new(TestEntity {
+            tag,
+            live: true,
+            bounds,
+            contacts: Vec::new(),
+        }))
+    }
+
+    fn get_test_entity(list: &EntityList, id: EntityId) -> Option<&TestEntity> {
+        list.get(id)
+            .map(|entity| entity.as_any().downcast_ref::<TestEntity>().unwrap())
+    }
+
+    fn get_tag(list: &EntityList, id: EntityId) -> Option<i32> {
+        get_test_entity(list, id).map(|entity| entity.tag)
+    }
+
+    fn kill(list: &mut EntityList, id: EntityId) {
+        let entity = list.get_mut(id).unwrap();
+        let any: &mut dyn Any = entity.as_mut();
+        any.downcast_mut::<TestEntity>().unwrap().live = false;
+    }
+
+    #[test]
+    fn test_entity_lookup() {
+        let mut list = EntityList::default();
+        let id1 = add_test_entity(&mut list, 1);
+        let id2 = add_test_entity(&mut list, 2);
+        let id3 = add_test_entity(&mut list, 3);
+        assert_eq!(get_tag(&list, id1), Some(1));
+        assert_eq!(get_tag(&list, id2), Some(2));
+        assert_eq!(get_tag(&list, id3), Some(3));
+
+        // Removing an entity makes its ID stale, but doesn't affect the
+        // others, even though they move within the list.
+        kill(&mut list, id1);
+        list.remove_dead();
+        assert_eq!(list.len(), 2);
+        assert!(!list.contains(id1));
+        assert_eq!(get_tag(&list, id1), None);
+        assert_eq!(get_tag(&list, id2), Some(2));
+        assert_eq!(get_tag(&list, id3), Some(3));
+        assert_eq!(list.ids(), &[id2, id3]);
+
+        let snapshot = list.snapshot(Some(id2));
+        assert_eq!(snapshot.player_rect().unwrap().left, 20);
+        assert!(snapshot.get_bounding_box(id1).is_none());
+    }
+
+    #[test]
+    fn test_stale_id_after_reuse() {
+        let mut list = EntityList::default();
+        let old_id = add_test_entity(&mut list, 1);
+        kill(&mut list, old_id);
+        list.remove_dead();
+
+        // The new entity reuses the freed slot, but the old ID must not find it.
+        let new_id = add_test_entity(&mut list, 2);
+        assert_ne!(old_id, new_id);
+        assert_eq!(get_tag(&list, old_id), None);
+        assert_eq!(get_tag(&list, new_id), Some(2));
+
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(get_tag(&list, new_id), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut list = EntityList::default();
+        let id1 = add_test_entity(&mut list, 1);
+        let id2 = add_test_entity(&mut list, 2);
+        let id3 = add_test_entity(&mut list, 3);
+
+        let entity = list.remove(id2).unwrap();
+        assert_eq!(entity.as_any().downcast_ref::<TestEntity>().unwrap().tag, 2);
+        assert!(list.remove(id2).is_none());
+        assert_eq!(list.ids(), &[id1, id3]);
+        assert_eq!(get_tag(&list, id1), Some(1));
+        assert_eq!(get_tag(&list, id3), Some(3));
+
+        // It can be added back with a new ID.
+        let new_id = list.add(entity);
+        assert_ne!(new_id, id2);
+        assert_eq!(get_tag(&list, new_id), Some(2));
+        assert_eq!(list.ids(), &[id1, id3, new_id]);
+    }
+
+    fn find_overlapping_pairs(boxes: &[util::Rect<i32>]) -> Vec<(usize, usize)> {
+        find_candidate_pairs(boxes)
+            .into_iter()
+            .filter(|(i, j)| boxes[*i].overlaps(&boxes[*j]))
+            .collect()
+    }
+
+    // Reference implementation: test every pair.
+    fn find_overlapping_pairs_brute_force(boxes: &[util::Rect<i32>]) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for i in 0..boxes.len() {
+            for j in i + 1..boxes.len() {
+                if boxes[i].overlaps(&boxes[j]) {
+                    pairs.push((i, j));
                 }
             }
         }
+
+        pairs
+    }
+
+    #[test]
+    fn test_broad_phase_matches_brute_force() {
+        let mut rng = util::GameRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let boxes: Vec<util::Rect<i32>> = (0..200)
+                .map(|_| {
+                    util::Rect::<i32>::new(
+                        rng.random_range(-200..1000),
+                        rng.random_range(-200..1000),
+                        rng.random_range(0..150),
+                        rng.random_range(0..150),
+                    )
+                })
+                .collect();
+
+            assert_eq!(
+                find_overlapping_pairs(&boxes),
+                find_overlapping_pairs_brute_force(&boxes)
+            );
+        }
+    }
+
+    #[test]
+    fn test_cell_boundaries() {
+        // Abutting exactly on a cell boundary does not overlap, but spanning
+        // it does.
+        let boxes = [
+            util::Rect::<i32>::new(CELL_SIZE - 10, 0, 10, 10),
+            util::Rect::<i32>::new(CELL_SIZE, 0, 10, 10),
+            util::Rect::<i32>::new(CELL_SIZE - 1, 5, 2, 2),
+            util::Rect::<i32>::new(-CELL_SIZE, 0, CELL_SIZE * 3, 1),
+        ];
+        assert_eq!(
+            find_overlapping_pairs(&boxes),
+            vec![(0, 2), (0, 3), (1, 2), (1, 3)]
+        );
+        assert_eq!(
+            find_overlapping_pairs(&boxes),
+            find_overlapping_pairs_brute_force(&boxes)
+        );
+    }
+
+    #[test]
+    fn test_contacts() {
+        let mut list = EntityList::default();
+        let id1 = add_test_entity_at(&mut list, 1, util::Rect::<i32>::new(0, 0, 10, 10));
+        let id2 = add_test_entity_at(&mut list, 2, util::Rect::<i32>::new(8, 2, 10, 10));
+        let id3 = add_test_entity_at(&mut list, 3, util::Rect::<i32>::new(-5, 9, 10, 10));
+        handle_collisions(&mut list);
+
+        // 1 is to the left of 2, and above 3.
+        assert_eq!(
+            get_test_entity(&list, id1).unwrap().contacts,
+            vec![
+                Contact {
+                    other_id: id2,
+                    other_class: 1,
+                    overlap: util::Rect::<i32>::new(8, 2, 2, 8),
+                    mtv: (-2, 0),
+                    landed: false,
+                },
+                Contact {
+                    other_id: id3,
+                    other_class: 1,
+                    overlap: util::Rect::<i32>::new(0, 9, 5, 1),
+                    mtv: (0, -1),
+                    landed: true,
+                },
+            ]
+        );
+        assert_eq!(
+            get_test_entity(&list, id2).unwrap().contacts,
+            vec![Contact {
+                other_id: id1,
+                other_class: 1,
+                overlap: util::Rect::<i32>::new(8, 2, 2, 8),
+                mtv: (2, 0),
+                landed: false,
+            }]
+        );
+        assert_eq!(
+            get_test_entity(&list, id3).unwrap().contacts,
+            vec![Contact {
+                other_id: id1,
+                other_class: 1,
+                overlap: util::Rect::<i32>::new(0, 9, 5, 1),
+                mtv: (0, 1),
+                landed: true,
+            }]
+        );
     }
 }
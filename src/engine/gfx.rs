@@ -14,8 +14,10 @@
 // limitations under the License.
 //
 
+use crate::raster;
 use gl::types::{GLint, GLsizeiptr, GLuint};
 use image::ImageReader;
+use std::path::{Path, PathBuf};
 
 pub type SpriteInfo = (f32, f32, f32, f32, i32, i32, i32, i32);
 
@@ -23,12 +25,31 @@ pub const WINDOW_WIDTH: i32 = 800;
 pub const WINDOW_HEIGHT: i32 = 450;
 
 pub struct RenderContext {
+    backend: Backend,
+    vertices: Vec<f32>,
+    offset: (i32, i32),
+    frame_count: u32,
+}
+
+// The vertex batch is built the same way regardless of backend, this only
+// determines what happens to it when render is called.
+enum Backend {
+    OpenGL(GlState),
+
+    // No window or GL context. Batches are discarded on render. This allows
+    // running the engine without a display (e.g. in tests).
+    Headless,
+
+    // No window or GL context. Batches are drawn on the CPU into an
+    // in-memory framebuffer.
+    Software(raster::Rasterizer),
+}
+
+struct GlState {
     window: sdl2::video::Window,
     _gl_context: sdl2::video::GLContext, // Hold this so it doesn't get dropped
     vbo: GLuint,
     atlas_texture_id: GLuint,
-    vertices: Vec<f32>,
-    offset: (i32, i32),
     position_attrib: GLuint,
     texcoord_attrib: GLuint,
 }
@@ -60,14 +81,14 @@ fn check_gl_error() {
     }
 }
 
-// We use a single texture atlas with all images to avoid state changes during
-// rendering.
-fn init_texture_atlas() -> GLuint {
-    // The atlas file is copied into the same directory as our executable.
+// The atlas file is copied into the same directory as our executable.
+pub fn get_atlas_path() -> PathBuf {
     let exe_path = std::env::current_exe().unwrap();
     let exe_dir = exe_path.parent().unwrap();
-    let atlas_path = exe_dir.join("atlas.png");
+    exe_dir.join("atlas.png")
+}
 
+fn load_atlas(atlas_path: &Path) -> image::RgbaImage {
     let img = ImageReader::open(atlas_path);
     if let Err(msg) = img {
         panic!("{}", msg);
@@ -78,12 +99,16 @@ fn init_texture_atlas() -> GLuint {
         panic!("{}", msg);
     }
 
-    let decoded = decode_result.unwrap();
-    let atlas_width = decoded.width();
-    let atlas_height = decoded.height();
+    decode_result.unwrap().into_rgba8()
+}
 
-    let binding = decoded.into_rgba8();
-    let raster_data = binding.as_raw();
+// We use a single texture atlas with all images to avoid state changes during
+// rendering.
+fn init_texture_atlas() -> GLuint {
+    let atlas = load_atlas(&get_atlas_path());
+    let atlas_width = atlas.width();
+    let atlas_height = atlas.height();
+    let raster_data = atlas.as_raw();
 
     unsafe {
         let mut atlas_texture_id: GLuint = 0;
@@ -172,17 +197,62 @@ impl RenderContext {
         check_gl_error();
 
         RenderContext {
-            window,
-            _gl_context: gl_context,
-            vbo,
-            atlas_texture_id,
+            backend: Backend::OpenGL(GlState {
+                window,
+                _gl_context: gl_context,
+                vbo,
+                atlas_texture_id,
+                position_attrib,
+                texcoord_attrib,
+            }),
             vertices: Vec::new(),
             offset: (0, 0),
-            position_attrib,
-            texcoord_attrib,
+            frame_count: 0,
         }
     }
 
+    pub fn new_headless() -> RenderContext {
+        RenderContext {
+            backend: Backend::Headless,
+            vertices: Vec::new(),
+            offset: (0, 0),
+            frame_count: 0,
+        }
+    }
+
+    // Render on the CPU using the atlas image at the given path. The result of
+    // the most recent call to render can be retrieved with framebuffer.
+    pub fn new_software(atlas_path: &Path) -> RenderContext {
+        RenderContext {
+            backend: Backend::Software(raster::Rasterizer::new(
+                load_atlas(atlas_path),
+                WINDOW_WIDTH as u32,
+                WINDOW_HEIGHT as u32,
+            )),
+            vertices: Vec::new(),
+            offset: (0, 0),
+            frame_count: 0,
+        }
+    }
+
+    // True if there is no window (so nothing is displayed).
+    pub fn is_headless(&self) -> bool {
+        !matches!(self.backend, Backend::OpenGL(_))
+    }
+
+    // The last rendered frame, if this is using the software backend.
+    pub fn framebuffer(&self) -> Option<&image::RgbaImage> {
+        match &self.backend {
+            Backend::Software(rasterizer) => Some(rasterizer.framebuffer()),
+            _ => None,
+        }
+    }
+
+    // Number of times render has been called.
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
     pub fn set_offset(&mut self, x: i32, y: i32) {
         self.offset = (x, y);
     }
@@ -277,6 +347,7 @@ impl RenderContext {
         );
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_quad(
         &mut self,
         dest_ul: (i32, i32),
@@ -316,14 +387,27 @@ impl RenderContext {
     }
 
     pub fn render(&mut self) {
+        match &mut self.backend {
+            Backend::OpenGL(gl_state) => gl_state.submit(&self.vertices),
+            Backend::Headless => {}
+            Backend::Software(rasterizer) => rasterizer.submit(&self.vertices),
+        }
+
+        self.vertices.clear();
+        self.frame_count += 1;
+    }
+}
+
+impl GlState {
+    fn submit(&self, vertices: &[f32]) {
         const ATTR_ELEMS_PER_VERTEX: usize = 4;
         unsafe {
             gl::Clear(gl::COLOR_BUFFER_BIT);
 
             gl::BufferData(
                 gl::ARRAY_BUFFER,
-                (self.vertices.len() * std::mem::size_of::<f32>()) as GLsizeiptr,
-                self.vertices.as_ptr().cast(),
+                std::mem::size_of_val(vertices) as GLsizeiptr,
+                vertices.as_ptr().cast(),
                 gl::STREAM_DRAW,
             );
 
@@ -355,14 +439,12 @@ impl RenderContext {
             gl::DrawArrays(
                 gl::TRIANGLES,
                 0,
-                (self.vertices.len() / ATTR_ELEMS_PER_VERTEX) as GLint,
+                (vertices.len() / ATTR_ELEMS_PER_VERTEX) as GLint,
             );
             check_gl_error();
         }
 
         self.window.gl_swap_window();
-
-        self.vertices.clear();
     }
 }
 
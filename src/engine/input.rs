@@ -0,0 +1,412 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//
+// Maps keyboard and game controller input to the CONTROL_* button mask that
+// entities see.
+//
+
+use crate::entity;
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::Keycode;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+const DEFAULT_DEADZONE: i16 = 8000;
+
+const ACTION_NAMES: [(&str, u32); 7] = [
+    ("up", entity::CONTROL_UP),
+    ("down", entity::CONTROL_DOWN),
+    ("left", entity::CONTROL_LEFT),
+    ("right", entity::CONTROL_RIGHT),
+    ("fire", entity::CONTROL_FIRE),
+    ("jump", entity::CONTROL_JUMP),
+    ("menu", entity::CONTROL_MENU),
+];
+
+// The analog stick always controls direction. deadzone is how far it must be
+// pushed (out of 32767) before it registers.
+pub struct InputBindings {
+    keys: HashMap<Keycode, u32>,
+    controller_buttons: HashMap<Button, u32>,
+    deadzone: i16,
+}
+
+impl Default for InputBindings {
+    fn default() -> InputBindings {
+        InputBindings {
+            keys: HashMap::from([
+                (Keycode::Up, entity::CONTROL_UP),
+                (Keycode::Down, entity::CONTROL_DOWN),
+                (Keycode::Left, entity::CONTROL_LEFT),
+                (Keycode::Right, entity::CONTROL_RIGHT),
+                (Keycode::X, entity::CONTROL_FIRE),
+                (Keycode::Z, entity::CONTROL_JUMP),
+                (Keycode::Escape, entity::CONTROL_MENU),
+            ]),
+            controller_buttons: HashMap::from([
+                (Button::DPadUp, entity::CONTROL_UP),
+                (Button::DPadDown, entity::CONTROL_DOWN),
+                (Button::DPadLeft, entity::CONTROL_LEFT),
+                (Button::DPadRight, entity::CONTROL_RIGHT),
+                (Button::X, entity::CONTROL_FIRE),
+                (Button::A, entity::CONTROL_JUMP),
+                (Button::Start, entity::CONTROL_MENU),
+            ]),
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn from_file(path: &Path) -> Result<InputBindings, String> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        InputBindings::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // Format is one action per line, followed by a comma separated list of
+    // the keys and controller buttons that trigger it. Keys use SDL key names,
+    // controller buttons are prefixed with "pad:" and use SDL game controller
+    // button names. For example:
+    //
+    //    # Comment
+    //    jump = Z, Space, pad:a
+    //    fire = X, pad:x, pad:rightshoulder
+    //    deadzone = 10000
+    //
+    // Actions that are not listed keep their default bindings. Errors
+    // include the line number so typos in the file are easy to find.
+    pub fn parse(contents: &str) -> Result<InputBindings, String> {
+        let mut bindings = InputBindings::default();
+        for (line_num, line) in contents.lines().enumerate() {
+            let line_num = line_num + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected 'action = inputs'", line_num));
+            };
+
+            let name = name.trim();
+            if name == "deadzone" {
+                bindings.deadzone = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid deadzone {}", line_num, value.trim()))?;
+                continue;
+            }
+
+            let Some((_, mask)) = ACTION_NAMES.iter().find(|(action, _)| *action == name) else {
+                return Err(format!("line {}: unknown action {}", line_num, name));
+            };
+
+            bindings.keys.retain(|_, action_mask| action_mask != mask);
+            bindings
+                .controller_buttons
+                .retain(|_, action_mask| action_mask != mask);
+            for input_name in value.split(',').map(|tok| tok.trim()) {
+                if let Some(button_name) = input_name.strip_prefix("pad:") {
+                    let Some(button) = Button::from_string(button_name) else {
+                        return Err(format!(
+                            "line {}: unknown controller button {}",
+                            line_num, button_name
+                        ));
+                    };
+                    bindings.controller_buttons.insert(button, *mask);
+                } else {
+                    let Some(key) = Keycode::from_name(input_name) else {
+                        return Err(format!("line {}: unknown key {}", line_num, input_name));
+                    };
+                    bindings.keys.insert(key, *mask);
+                }
+            }
+        }
+
+        Ok(bindings)
+    }
+}
+
+// Tracks the state of every bound input. Each input is tracked separately
+// so, if several are bound to the same action, releasing one doesn't
+// release the action while the others are still held.
+#[derive(Default)]
+pub struct InputMapper {
+    bindings: InputBindings,
+    held_keys: HashSet<Keycode>,
+    held_controller_buttons: HashSet<Button>,
+    stick_x: i16,
+    stick_y: i16,
+}
+
+impl InputMapper {
+    pub fn new(bindings: InputBindings) -> InputMapper {
+        InputMapper {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    pub fn key_down(&mut self, key: Keycode) {
+        self.held_keys.insert(key);
+    }
+
+    pub fn key_up(&mut self, key: Keycode) {
+        self.held_keys.remove(&key);
+    }
+
+    pub fn controller_button_down(&mut self, button: Button) {
+        self.held_controller_buttons.insert(button);
+    }
+
+    pub fn controller_button_up(&mut self, button: Button) {
+        self.held_controller_buttons.remove(&button);
+    }
+
+    pub fn controller_axis_motion(&mut self, axis: Axis, value: i16) {
+        match axis {
+            Axis::LeftX => self.stick_x = value,
+            Axis::LeftY => self.stick_y = value,
+            _ => {}
+        }
+    }
+
+    pub fn get_buttons(&self) -> u32 {
+        let mut buttons = 0;
+        for key in &self.held_keys {
+            buttons |= self.bindings.keys.get(key).copied().unwrap_or(0);
+        }
+
+        for button in &self.held_controller_buttons {
+            buttons |= self
+                .bindings
+                .controller_buttons
+                .get(button)
+                .copied()
+                .unwrap_or(0);
+        }
+
+        let deadzone = self.bindings.deadzone;
+        if self.stick_x < -deadzone {
+            buttons |= entity::CONTROL_LEFT;
+        } else if self.stick_x > deadzone {
+            buttons |= entity::CONTROL_RIGHT;
+        }
+
+        if self.stick_y < -deadzone {
+            buttons |= entity::CONTROL_UP;
+        } else if self.stick_y > deadzone {
+            buttons |= entity::CONTROL_DOWN;
+        }
+
+        buttons
+    }
+}
+
+// Snapshot of the buttons for a single simulation step, along with enough
+// history to detect when they change. Each query takes a mask of CONTROL_*
+// bits and is true if it applies to any of them.
+#[derive(Default, Clone)]
+pub struct InputState {
+    buttons: u32,
+    prev_buttons: u32,
+    hold_times: [f32; 32],
+}
+
+impl InputState {
+    // Called once at the beginning of each simulation step with the current
+    // button state.
+    pub fn update(&mut self, buttons: u32, d_t: f32) {
+        self.prev_buttons = self.buttons;
+        self.buttons = buttons;
+        for (bit, hold_time) in self.hold_times.iter_mut().enumerate() {
+            if buttons & (1 << bit) != 0 {
+                *hold_time += d_t;
+            } else {
+                *hold_time = 0.0;
+            }
+        }
+    }
+
+    pub fn buttons(&self) -> u32 {
+        self.buttons
+    }
+
+    // Went down this step.
+    pub fn pressed(&self, mask: u32) -> bool {
+        (self.buttons & !self.prev_buttons & mask) != 0
+    }
+
+    // Went up this step.
+    pub fn released(&self, mask: u32) -> bool {
+        (!self.buttons & self.prev_buttons & mask) != 0
+    }
+
+    pub fn held(&self, mask: u32) -> bool {
+        (self.buttons & mask) != 0
+    }
+
+    // How long (in seconds) the button has been continuously held, including
+    // this step. Zero if it is not held.
+    pub fn held_time(&self, mask: u32) -> f32 {
+        self.hold_times
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .map(|(_, hold_time)| *hold_time)
+            .fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_bindings() {
+        let mut mapper = InputMapper::default();
+        mapper.key_down(Keycode::Z);
+        mapper.key_down(Keycode::Left);
+        assert_eq!(
+            mapper.get_buttons(),
+            entity::CONTROL_JUMP | entity::CONTROL_LEFT
+        );
+
+        mapper.key_up(Keycode::Z);
+        mapper.controller_button_down(Button::X);
+        assert_eq!(
+            mapper.get_buttons(),
+            entity::CONTROL_FIRE | entity::CONTROL_LEFT
+        );
+    }
+
+    #[test]
+    fn test_multiple_keys() {
+        let mut mapper = InputMapper::new(
+            InputBindings::parse("# AZERTY layout\njump = W, Space, pad:b\nfire = Q\n").unwrap(),
+        );
+
+        // The old binding is replaced.
+        mapper.key_down(Keycode::Z);
+        assert_eq!(mapper.get_buttons(), 0);
+
+        mapper.key_down(Keycode::W);
+        mapper.key_down(Keycode::Space);
+        mapper.key_up(Keycode::W);
+        assert_eq!(mapper.get_buttons(), entity::CONTROL_JUMP);
+        mapper.key_up(Keycode::Space);
+        assert_eq!(mapper.get_buttons(), 0);
+
+        mapper.controller_button_down(Button::B);
+        mapper.key_down(Keycode::Q);
+        assert_eq!(
+            mapper.get_buttons(),
+            entity::CONTROL_JUMP | entity::CONTROL_FIRE
+        );
+
+        // Unchanged actions keep default bindings
+        mapper.key_down(Keycode::Escape);
+        assert_eq!(
+            mapper.get_buttons(),
+            entity::CONTROL_JUMP | entity::CONTROL_FIRE | entity::CONTROL_MENU
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            InputBindings::parse("# Comment\njump Z").err().unwrap(),
+            "line 2: expected 'action = inputs'"
+        );
+        assert_eq!(
+            InputBindings::parse("jmup = Z").err().unwrap(),
+            "line 1: unknown action jmup"
+        );
+        assert_eq!(
+            InputBindings::parse("jump = Z\n\nfire = NotAKey")
+                .err()
+                .unwrap(),
+            "line 3: unknown key NotAKey"
+        );
+        assert_eq!(
+            InputBindings::parse("fire = pad:nope").err().unwrap(),
+            "line 1: unknown controller button nope"
+        );
+        assert_eq!(
+            InputBindings::parse("deadzone = lots").err().unwrap(),
+            "line 1: invalid deadzone lots"
+        );
+    }
+
+    #[test]
+    fn test_stick_deadzone() {
+        let mut mapper = InputMapper::new(InputBindings::parse("deadzone = 10000").unwrap());
+        mapper.controller_axis_motion(Axis::LeftX, 9000);
+        mapper.controller_axis_motion(Axis::LeftY, -9000);
+        assert_eq!(mapper.get_buttons(), 0);
+
+        mapper.controller_axis_motion(Axis::LeftX, 11000);
+        assert_eq!(mapper.get_buttons(), entity::CONTROL_RIGHT);
+
+        mapper.controller_axis_motion(Axis::LeftX, -11000);
+        mapper.controller_axis_motion(Axis::LeftY, -11000);
+        assert_eq!(
+            mapper.get_buttons(),
+            entity::CONTROL_LEFT | entity::CONTROL_UP
+        );
+
+        mapper.controller_axis_motion(Axis::LeftY, 32767);
+        assert_eq!(
+            mapper.get_buttons(),
+            entity::CONTROL_LEFT | entity::CONTROL_DOWN
+        );
+    }
+
+    #[test]
+    fn test_input_state_edges() {
+        let mut state = InputState::default();
+        state.update(entity::CONTROL_JUMP, 0.25);
+        assert!(state.pressed(entity::CONTROL_JUMP));
+        assert!(state.pressed(entity::CONTROL_JUMP | entity::CONTROL_FIRE));
+        assert!(!state.pressed(entity::CONTROL_FIRE));
+        assert!(state.held(entity::CONTROL_JUMP));
+        assert!(!state.released(entity::CONTROL_JUMP));
+        assert_eq!(state.held_time(entity::CONTROL_JUMP), 0.25);
+
+        state.update(entity::CONTROL_JUMP | entity::CONTROL_FIRE, 0.25);
+        assert!(!state.pressed(entity::CONTROL_JUMP));
+        assert!(state.pressed(entity::CONTROL_FIRE));
+        assert!(state.held(entity::CONTROL_JUMP));
+        assert_eq!(state.held_time(entity::CONTROL_JUMP), 0.5);
+        assert_eq!(state.held_time(entity::CONTROL_FIRE), 0.25);
+        assert_eq!(
+            state.held_time(entity::CONTROL_JUMP | entity::CONTROL_FIRE),
+            0.5
+        );
+
+        state.update(entity::CONTROL_FIRE, 0.25);
+        assert!(state.released(entity::CONTROL_JUMP));
+        assert!(!state.released(entity::CONTROL_FIRE));
+        assert!(!state.held(entity::CONTROL_JUMP));
+        assert_eq!(state.held_time(entity::CONTROL_JUMP), 0.0);
+
+        state.update(entity::CONTROL_FIRE, 0.25);
+        assert!(!state.released(entity::CONTROL_JUMP));
+        assert_eq!(state.buttons(), entity::CONTROL_FIRE);
+    }
+}
@@ -17,93 +17,493 @@
 pub mod audio;
 pub mod entity;
 pub mod gfx;
+pub mod input;
+pub mod pathfind;
+mod raster;
+pub mod replay;
+pub mod scene;
+pub mod spawner;
 pub mod tilemap;
+pub mod trigger;
 pub mod ui;
 pub mod util;
 extern crate sdl2;
+use rand::SeedableRng;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 pub const LEFT_SCROLL_BOUNDARY: i32 = gfx::WINDOW_WIDTH / 3;
 pub const RIGHT_SCROLL_BOUNDARY: i32 = gfx::WINDOW_WIDTH * 2 / 3;
 pub const TOP_SCROLL_BOUNDARY: i32 = gfx::WINDOW_HEIGHT / 3;
 pub const BOTTOM_SCROLL_BOUNDARY: i32 = gfx::WINDOW_HEIGHT * 2 / 3;
 
-pub type EntityCreateFn = fn(i32, i32) -> Box<dyn entity::Entity>;
+// The simulation always advances in steps of this size, regardless of the
+// display refresh rate.
+pub const TIME_STEP: f32 = 1.0 / 60.0;
+
+// The first few calls to poll events in SDL take a significantly longer
+// period of time. Rather than trying to catch up all at once (which would
+// cause a visible jump), drop the excess time.
+const MAX_STEPS_PER_FRAME: u32 = 5;
+
+// Default for GameEngine::spawn_margin.
+const SPAWN_MARGIN: i32 = tilemap::TILE_SIZE * 4;
+
+pub type EntityCreateFn = fn(&tilemap::MapObject) -> Box<dyn entity::Entity>;
+pub type TriggerFn = fn(&mut GameEngine, &tilemap::MapObject, trigger::TriggerEvent);
+
+// What has changed in a map the player has left.
+struct MapState {
+    tile_edits: Vec<tilemap::TileEdit>,
+    saved_objects: HashMap<usize, spawner::SavedObject>,
+}
 
 pub struct GameEngine {
-    _sdl: sdl2::Sdl,
+    // These are None when running headless.
+    _sdl: Option<sdl2::Sdl>,
+    event_pump: Option<sdl2::EventPump>,
+    controller_subsystem: Option<sdl2::GameControllerSubsystem>,
+    controllers: Vec<sdl2::controller::GameController>,
+    input_mapper: input::InputMapper,
     pub render_context: gfx::RenderContext,
     pub tile_map: tilemap::TileMap,
-    event_pump: sdl2::EventPump,
-    pub entities: Vec<Box<dyn entity::Entity>>,
+
+    // Maps are loaded from this directory, which is where the executable is
+    // by default. Changes to maps the player has left are kept by map name,
+    // so they are the same on return.
+    pub map_dir: PathBuf,
+    map_name: String,
+    map_states: HashMap<String, MapState>,
+
+    // Destination map and spawn point name (see change_map).
+    pending_map_change: Option<(String, String)>,
+    pub entities: entity::EntityList,
+    pub player_id: Option<entity::EntityId>,
+    scenes: Vec<Box<dyn scene::Scene>>,
+    pub x_scroll: i32,
+    pub y_scroll: i32,
+    prev_x_scroll: i32,
+    prev_y_scroll: i32,
     pub max_x_scroll: i32,
     pub max_y_scroll: i32,
     pub entity_fns: HashMap<String, EntityCreateFn>,
+    spawner: spawner::ObjectSpawner,
+
+    // Entities for map objects are created when they are this many pixels
+    // outside the visible area (see spawner.rs).
+    pub spawn_margin: i32,
+    pub trigger_fns: HashMap<String, TriggerFn>,
+    trigger_tracker: trigger::TriggerTracker,
     pub buttons: u32,
+    pub input: input::InputState,
     pub quit: bool,
-}
-
-fn get_key_mask(key: sdl2::keyboard::Keycode) -> u32 {
-    match key {
-        sdl2::keyboard::Keycode::Up => entity::CONTROL_UP,
-        sdl2::keyboard::Keycode::Down => entity::CONTROL_DOWN,
-        sdl2::keyboard::Keycode::Left => entity::CONTROL_LEFT,
-        sdl2::keyboard::Keycode::Right => entity::CONTROL_RIGHT,
-        sdl2::keyboard::Keycode::X => entity::CONTROL_FIRE,
-        sdl2::keyboard::Keycode::Z => entity::CONTROL_JUMP,
-        sdl2::keyboard::Keycode::Escape => entity::CONTROL_MENU,
-        _ => 0,
-    }
+    last_frame_time: Option<std::time::Instant>,
+    accumulator: f32,
+    pub rng: util::GameRng,
+    seed: u64,
+    recorder: Option<replay::InputRecorder>,
+    playback: Option<replay::InputPlayback>,
 }
 
 impl GameEngine {
     pub fn new(audio_file_list: &[&str]) -> GameEngine {
         let sdl = sdl2::init().unwrap();
         audio::init_audio(audio_file_list);
+        let render_context = gfx::RenderContext::new(&sdl);
+        GameEngine::create(Some(sdl), render_context, rand::random::<u64>())
+    }
 
+    // Create an engine that doesn't open a window or audio device. Rendering
+    // and sound calls are accepted but discarded, and there are no input
+    // events, so the caller must set buttons directly. This is intended for
+    // tests: audio is global, so this also silences any other engine in the
+    // process until one is created with new.
+    pub fn new_headless() -> GameEngine {
+        audio::init_null_audio();
+        GameEngine::create(None, gfx::RenderContext::new_headless(), 0)
+    }
+
+    // sdl is None when running headless.
+    fn create(sdl: Option<sdl2::Sdl>, render_context: gfx::RenderContext, seed: u64) -> GameEngine {
         GameEngine {
-            render_context: gfx::RenderContext::new(&sdl),
+            render_context,
             tile_map: tilemap::TileMap::default(),
-            event_pump: sdl.event_pump().unwrap(),
-            entities: Vec::new(),
+            map_dir: get_exe_dir(),
+            map_name: String::new(),
+            map_states: HashMap::new(),
+            pending_map_change: None,
+            event_pump: sdl.as_ref().map(|sdl| sdl.event_pump().unwrap()),
+            controller_subsystem: sdl.as_ref().map(|sdl| sdl.game_controller().unwrap()),
+            controllers: Vec::new(),
+            input_mapper: input::InputMapper::default(),
+            entities: entity::EntityList::default(),
+            player_id: None,
+            scenes: Vec::new(),
             _sdl: sdl,
+            x_scroll: 0,
+            y_scroll: 0,
+            prev_x_scroll: 0,
+            prev_y_scroll: 0,
             max_x_scroll: 0,
             max_y_scroll: 0,
             entity_fns: HashMap::new(),
+            spawner: spawner::ObjectSpawner::default(),
+            spawn_margin: SPAWN_MARGIN,
+            trigger_fns: HashMap::new(),
+            trigger_tracker: trigger::TriggerTracker::default(),
             buttons: 0,
+            input: input::InputState::default(),
             quit: false,
+            last_frame_time: None,
+            accumulator: 0.0,
+            rng: util::GameRng::seed_from_u64(seed),
+            seed,
+            recorder: None,
+            playback: None,
+        }
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = util::GameRng::seed_from_u64(seed);
+    }
+
+    // Write the button state for each simulation step to a file. This should
+    // be called before anything uses the RNG, since the RNG is reseeded so
+    // the recording can be reproduced from the start.
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), String> {
+        self.seed_rng(self.seed);
+        self.recorder = Some(replay::InputRecorder::new(path, self.seed)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.flush();
         }
+
+        self.recorder = None;
+    }
+
+    // Take button states from a file created by start_recording rather than
+    // from input events. When the recording ends, control returns to the
+    // player.
+    pub fn start_playback(&mut self, path: &Path) -> Result<(), String> {
+        let playback = replay::InputPlayback::new(path)?;
+        self.seed_rng(playback.seed);
+        self.playback = Some(playback);
+        Ok(())
+    }
+
+    pub fn is_playing_back(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn set_input_bindings(&mut self, bindings: input::InputBindings) {
+        self.input_mapper = input::InputMapper::new(bindings);
+    }
+
+    // Latch the current buttons for this simulation step, for use by the
+    // queries below and by entities. This is called automatically by
+    // run_frame.
+    pub fn update_input(&mut self, d_t: f32) {
+        self.input.update(self.buttons, d_t);
+    }
+
+    pub fn pressed(&self, mask: u32) -> bool {
+        self.input.pressed(mask)
+    }
+
+    pub fn released(&self, mask: u32) -> bool {
+        self.input.released(mask)
+    }
+
+    pub fn held(&self, mask: u32) -> bool {
+        self.input.held(mask)
+    }
+
+    pub fn held_time(&self, mask: u32) -> f32 {
+        self.input.held_time(mask)
     }
 
     pub fn register_entity(&mut self, name: &str, create_fn: EntityCreateFn) {
         self.entity_fns.insert(name.to_string(), create_fn);
     }
 
-    pub fn load_tile_map(&mut self, file_name: &str) {
-        let exe_path = std::env::current_exe().unwrap();
-        let exe_dir = exe_path.parent().unwrap();
-        let tile_map_path = exe_dir.join(file_name);
-        self.tile_map = tilemap::TileMap::new(&tile_map_path);
+    // Call trigger_fn when the player enters, stays in, or leaves any
+    // trigger with this name. Triggers with no function registered are
+    // ignored.
+    pub fn register_trigger(&mut self, name: &str, trigger_fn: TriggerFn) {
+        self.trigger_fns.insert(name.to_string(), trigger_fn);
+    }
+
+    // Load a map built by build_assets.rs, e.g. "map" for assets/maps/map.tmx.
+    pub fn load_tile_map(&mut self, map_name: &str) {
+        let tile_map_path = self.map_dir.join(format!("{}.bin", map_name));
+        self.set_tile_map(tilemap::TileMap::new(&tile_map_path));
+        self.map_name = map_name.to_string();
+    }
+
+    // Move the player to the named spawn point in another map (or its player
+    // start if spawn_name is empty). This happens at the end of the current
+    // step, so it can be called from entity or trigger updates. The player
+    // entity is carried over as is, and the other entities are replaced with
+    // the new map's.
+    pub fn change_map(&mut self, map_name: &str, spawn_name: &str) {
+        self.pending_map_change = Some((map_name.to_string(), spawn_name.to_string()));
+    }
+
+    fn finish_map_change(&mut self, map_name: &str, spawn_name: &str) {
+        let player = self.player_id.and_then(|id| self.entities.remove(id));
+        let saved_objects = self.spawner.save(&self.entities);
+        self.entities.clear();
+        self.player_id = None;
+        self.map_states.insert(
+            self.map_name.clone(),
+            MapState {
+                tile_edits: self.tile_map.get_modifications(),
+                saved_objects,
+            },
+        );
+
+        self.load_tile_map(map_name);
+        if let Some(state) = self.map_states.get(map_name) {
+            self.tile_map.restore_modifications(&state.tile_edits);
+            self.tile_map.take_dirty_region();
+        }
+
+        // Warps in the map are checked by build_assets.rs, so this can only
+        // fail if change_map was called directly with a bad name.
+        let (x, y) = self
+            .tile_map
+            .find_spawn_point(spawn_name)
+            .unwrap_or_else(|| panic!("map {} has no spawn point {}", map_name, spawn_name));
+        if let Some(mut player) = player {
+            player.move_to(x as f32, y as f32);
+            self.player_id = Some(self.entities.add(player));
+        }
+
+        self.create_entities();
+        self.center_camera();
+
+        // Don't fire triggers the player arrives inside of (such as a warp
+        // back) until they leave and come back.
+        let player_rect = self.get_player().map(|player| player.get_bounding_box());
+        self.trigger_tracker
+            .update(&self.tile_map.triggers, player_rect.as_ref());
+    }
+
+    pub fn set_tile_map(&mut self, tile_map: tilemap::TileMap) {
+        self.tile_map = tile_map;
         self.max_x_scroll = self.tile_map.width * tilemap::TILE_SIZE - gfx::WINDOW_WIDTH;
         self.max_y_scroll = self.tile_map.height * tilemap::TILE_SIZE - gfx::WINDOW_HEIGHT;
+        self.trigger_tracker.reset();
+
+        // Nothing is spawned from the new map until create_entities is called.
+        self.spawner.reset(0, HashMap::new());
     }
 
+    // Create entities for the objects in the map. Only the ones near the
+    // camera are created now. The rest are created by update_entities as the
+    // camera gets close to them.
     pub fn create_entities(&mut self) {
-        self.entities
-            .extend(self.tile_map.objects.iter().map(|(name, x, y)| {
-                let create_fn = self.entity_fns.get(name).unwrap();
-                create_fn(*x, *y)
-            }));
+        let saved = self
+            .map_states
+            .get_mut(&self.map_name)
+            .map(|state| std::mem::take(&mut state.saved_objects))
+            .unwrap_or_default();
+        self.spawner.reset(self.tile_map.objects.len(), saved);
+        self.update_spawner();
+    }
+
+    fn update_spawner(&mut self) {
+        let visible_rect = self.visible_rect();
+        self.spawner.update(
+            &self.tile_map.objects,
+            &self.entity_fns,
+            &mut self.entities,
+            self.player_id,
+            &visible_rect,
+            self.spawn_margin,
+        );
+    }
+
+    // Add an entity to the world. The returned ID can be used to look it up
+    // later with get_entity.
+    pub fn spawn(&mut self, entity: Box<dyn entity::Entity>) -> entity::EntityId {
+        self.entities.add(entity)
     }
 
-    // This needs to be called before run, as the player is the first entity in the list.
-    pub fn spawn_player(&mut self, create_fn: EntityCreateFn) {
-        let entity = create_fn(self.tile_map.player_start_x, self.tile_map.player_start_y);
-        self.entities.push(entity);
+    pub fn spawn_player(&mut self, create_fn: EntityCreateFn) -> entity::EntityId {
+        let entity = create_fn(&tilemap::MapObject::new(
+            "Player",
+            self.tile_map.player_start_x,
+            self.tile_map.player_start_y,
+        ));
+        let id = self.spawn(entity);
+        self.player_id = Some(id);
+        id
+    }
+
+    // Returns None if the entity has been removed.
+    pub fn get_entity(&self, id: entity::EntityId) -> Option<&dyn entity::Entity> {
+        self.entities.get(id)
+    }
+
+    pub fn get_player(&self) -> Option<&dyn entity::Entity> {
+        self.entities.get(self.player_id?)
+    }
+
+    // Scroll the view to keep the player inside the scroll boundaries.
+    pub fn update_camera(&mut self) {
+        self.prev_x_scroll = self.x_scroll;
+        self.prev_y_scroll = self.y_scroll;
+
+        let Some(player) = self.get_player() else {
+            return;
+        };
+
+        let player_rect = player.get_bounding_box();
+        if player_rect.right() > self.x_scroll + RIGHT_SCROLL_BOUNDARY {
+            self.x_scroll = std::cmp::min(
+                player_rect.right() - RIGHT_SCROLL_BOUNDARY,
+                self.max_x_scroll,
+            );
+        } else if player_rect.left < self.x_scroll + LEFT_SCROLL_BOUNDARY {
+            self.x_scroll = std::cmp::max(0, player_rect.left - LEFT_SCROLL_BOUNDARY);
+        }
+
+        if player_rect.bottom() > self.y_scroll + BOTTOM_SCROLL_BOUNDARY {
+            self.y_scroll = std::cmp::min(
+                player_rect.bottom() - BOTTOM_SCROLL_BOUNDARY,
+                self.max_y_scroll,
+            );
+        } else if player_rect.top < self.y_scroll + TOP_SCROLL_BOUNDARY {
+            self.y_scroll = std::cmp::max(0, player_rect.top - TOP_SCROLL_BOUNDARY);
+        }
+    }
+
+    // Jump directly to the player's position, e.g. after changing maps.
+    pub fn center_camera(&mut self) {
+        let Some(player) = self.get_player() else {
+            return;
+        };
+
+        let player_rect = player.get_bounding_box();
+        self.x_scroll = (player_rect.left + player_rect.width / 2 - gfx::WINDOW_WIDTH / 2)
+            .clamp(0, self.max_x_scroll);
+        self.y_scroll = (player_rect.top + player_rect.height / 2 - gfx::WINDOW_HEIGHT / 2)
+            .clamp(0, self.max_y_scroll);
+        self.prev_x_scroll = self.x_scroll;
+        self.prev_y_scroll = self.y_scroll;
+    }
+
+    pub fn visible_rect(&self) -> util::Rect<i32> {
+        util::Rect::<i32>::new(
+            self.x_scroll,
+            self.y_scroll,
+            gfx::WINDOW_WIDTH,
+            gfx::WINDOW_HEIGHT,
+        )
+    }
+
+    // Draw the tile map and all entities. alpha is how far we are between
+    // the previous simulation step and the current one (0.0-1.0), and is
+    // used to smooth out motion.
+    pub fn draw_world(&mut self, alpha: f32) {
+        let x_scroll = util::lerp(self.prev_x_scroll as f32, self.x_scroll as f32, alpha) as i32;
+        let y_scroll = util::lerp(self.prev_y_scroll as f32, self.y_scroll as f32, alpha) as i32;
+        self.render_context.set_offset(x_scroll, y_scroll);
+
+        let visible_rect =
+            util::Rect::<i32>::new(x_scroll, y_scroll, gfx::WINDOW_WIDTH, gfx::WINDOW_HEIGHT);
+        self.tile_map
+            .draw_background(&mut self.render_context, &visible_rect);
+        self.entities.iter().for_each(|entity| {
+            entity.draw(&mut self.render_context, alpha);
+        });
+        self.tile_map
+            .draw_foreground(&mut self.render_context, &visible_rect);
+    }
+
+    // Run one simulation step: dispatch collisions, update all entities,
+    // then add newly spawned ones and remove dead ones. Entities for map
+    // objects are also created or removed here, depending on how close they
+    // are to the camera, and other entities that are far away are removed.
+    pub fn update_entities(&mut self, d_t: f32) {
+        let world = self.entities.snapshot(self.player_id);
+        entity::handle_collisions(&mut self.entities);
+
+        let mut new_entities: Vec<Box<dyn entity::Entity>> = Vec::new();
+        let mut tile_edits = Vec::new();
+        let mut ctx = entity::UpdateContext {
+            d_t,
+            new_entities: &mut new_entities,
+            tile_edits: &mut tile_edits,
+            input: &self.input,
+            tile_map: &self.tile_map,
+            world: &world,
+            rng: &mut self.rng,
+        };
+        self.entities.iter_mut().for_each(|entity| {
+            entity.update(&mut ctx);
+        });
+
+        for entity in new_entities {
+            self.entities.add(entity);
+        }
+
+        // Edits outside the map or with invalid tiles are dropped.
+        for edit in tile_edits {
+            self.tile_map.set_tile(edit.x, edit.y, edit.tile);
+        }
+
+        if let Some(region) = self.tile_map.take_dirty_region() {
+            for entity in self.entities.iter_mut() {
+                entity.tiles_changed(&region);
+            }
+        }
+
+        self.entities.remove_dead();
+        self.update_spawner();
+        self.update_triggers();
+        if let Some((map_name, spawn_name)) = self.pending_map_change.take() {
+            self.finish_map_change(&map_name, &spawn_name);
+        }
+    }
+
+    fn update_triggers(&mut self) {
+        let player_rect = self.get_player().map(|player| player.get_bounding_box());
+        let events = self
+            .trigger_tracker
+            .update(&self.tile_map.triggers, player_rect.as_ref());
+
+        // Callbacks can replace the tile map (and its triggers), so copy the
+        // ones that fired before calling any.
+        let fired: Vec<(tilemap::MapObject, trigger::TriggerEvent)> = events
+            .into_iter()
+            .map(|(index, event)| (self.tile_map.triggers[index].clone(), event))
+            .collect();
+        for (trigger, event) in fired {
+            if trigger.type_name == tilemap::WARP_TYPE {
+                if event == trigger::TriggerEvent::Enter {
+                    self.change_map(
+                        trigger.get_string("map").expect("warp has no map"),
+                        trigger.get_string("spawn").unwrap_or_default(),
+                    );
+                }
+            } else if let Some(trigger_fn) = self.trigger_fns.get(&trigger.name).copied() {
+                trigger_fn(self, &trigger, event);
+            }
+        }
     }
 
     pub fn poll_events(&mut self) {
-        for event in self.event_pump.poll_iter() {
+        let Some(event_pump) = self.event_pump.as_mut() else {
+            return;
+        };
+
+        for event in event_pump.poll_iter() {
             match event {
                 sdl2::event::Event::Quit { .. } => self.quit = true,
                 sdl2::event::Event::KeyDown {
@@ -111,18 +511,181 @@ impl GameEngine {
                     repeat: false,
                     ..
                 } => {
-                    self.buttons |= get_key_mask(keycode);
+                    self.input_mapper.key_down(keycode);
                 }
 
                 sdl2::event::Event::KeyUp {
                     keycode: Some(keycode),
                     ..
                 } => {
-                    self.buttons &= !get_key_mask(keycode);
+                    self.input_mapper.key_up(keycode);
+                }
+
+                sdl2::event::Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(subsystem) = self.controller_subsystem.as_ref() {
+                        match subsystem.open(which) {
+                            Ok(controller) => self.controllers.push(controller),
+                            Err(msg) => eprintln!("Unable to open controller: {}", msg),
+                        }
+                    }
+                }
+
+                sdl2::event::Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers
+                        .retain(|controller| controller.instance_id() != which);
+                }
+
+                sdl2::event::Event::ControllerButtonDown { button, .. } => {
+                    self.input_mapper.controller_button_down(button);
+                }
+
+                sdl2::event::Event::ControllerButtonUp { button, .. } => {
+                    self.input_mapper.controller_button_up(button);
+                }
+
+                sdl2::event::Event::ControllerAxisMotion { axis, value, .. } => {
+                    self.input_mapper.controller_axis_motion(axis, value);
                 }
 
                 _ => {}
             }
         }
+
+        self.buttons = self.input_mapper.get_buttons();
+    }
+
+    // Run the main loop until the scene stack is empty or the window is
+    // closed.
+    pub fn run(&mut self, initial_scene: Box<dyn scene::Scene>) {
+        self.push_scene(initial_scene);
+        while self.run_frame() {}
+
+        self.stop_recording();
+
+        while !self.scenes.is_empty() {
+            self.pop_scene();
+        }
+    }
+
+    // Process input, run as many simulation steps as are needed to catch
+    // up with real time, and draw a single frame. Returns false when the game
+    // should exit.
+    pub fn run_frame(&mut self) -> bool {
+        self.poll_events();
+        if self.quit {
+            return false;
+        }
+
+        self.accumulator += self.get_frame_time();
+        let mut steps = 0;
+        while self.accumulator >= TIME_STEP {
+            if steps == MAX_STEPS_PER_FRAME {
+                self.accumulator = 0.0;
+                break;
+            }
+
+            self.accumulator -= TIME_STEP;
+            steps += 1;
+            self.update_scene();
+            if self.quit || self.scenes.is_empty() {
+                return false;
+            }
+        }
+
+        self.draw_scenes(self.accumulator / TIME_STEP);
+        self.render_context.render();
+
+        true
     }
+
+    // Return the amount of real time that has elapsed since the last frame.
+    // When running headless, there is no display to keep pace with, so each
+    // frame is exactly one simulation step. This makes runs reproducible.
+    fn get_frame_time(&mut self) -> f32 {
+        if self.render_context.is_headless() {
+            return TIME_STEP;
+        }
+
+        let now = std::time::Instant::now();
+        let elapsed = match self.last_frame_time {
+            Some(last_time) => (now - last_time).as_secs_f32(),
+            None => TIME_STEP,
+        };
+
+        self.last_frame_time = Some(now);
+        elapsed
+    }
+
+    fn update_scene(&mut self) {
+        if let Some(playback) = self.playback.as_mut() {
+            match playback.next_buttons() {
+                Some(buttons) => self.buttons = buttons,
+                None => {
+                    // Return control to the player.
+                    self.playback = None;
+                    self.buttons = self.input_mapper.get_buttons();
+                }
+            }
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(self.buttons);
+        }
+
+        self.update_input(TIME_STEP);
+
+        // The scene is temporarily removed from the stack so it can be
+        // passed a mutable reference to the engine.
+        let Some(mut current) = self.scenes.pop() else {
+            return;
+        };
+
+        let transition = current.update(self, TIME_STEP);
+        self.scenes.push(current);
+        match transition {
+            scene::SceneTransition::None => {}
+            scene::SceneTransition::Push(new_scene) => self.push_scene(new_scene),
+            scene::SceneTransition::Pop => self.pop_scene(),
+            scene::SceneTransition::Replace(new_scene) => {
+                self.pop_scene();
+                self.push_scene(new_scene);
+            }
+            scene::SceneTransition::Quit => self.quit = true,
+        }
+    }
+
+    pub fn push_scene(&mut self, mut new_scene: Box<dyn scene::Scene>) {
+        new_scene.on_enter(self);
+        self.scenes.push(new_scene);
+    }
+
+    fn pop_scene(&mut self) {
+        if let Some(mut old_scene) = self.scenes.pop() {
+            old_scene.on_exit(self);
+        }
+    }
+
+    // Draw the top scene, along with any scenes beneath it that are visible
+    // because the ones above are overlays.
+    fn draw_scenes(&mut self, alpha: f32) {
+        // The scenes are taken off the stack so they can be passed a mutable
+        // reference to the engine. Any scenes pushed while drawing go on top
+        // of them afterwards.
+        let scenes = std::mem::take(&mut self.scenes);
+        let first_visible = scenes
+            .iter()
+            .rposition(|scene| !scene.is_overlay())
+            .unwrap_or(0);
+        for scene in &scenes[first_visible..] {
+            scene.draw(self, alpha);
+        }
+
+        let pushed = std::mem::replace(&mut self.scenes, scenes);
+        self.scenes.extend(pushed);
+    }
+}
+
+fn get_exe_dir() -> PathBuf {
+    let exe_path = std::env::current_exe().unwrap();
+    exe_path.parent().unwrap().to_path_buf()
 }
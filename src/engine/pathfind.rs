@@ -0,0 +1,439 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+// A* search over the tile grid, so enemies can find their way around walls.
+// Positions and paths are in tile coordinates.
+
+use crate::tilemap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+pub type TilePos = (i32, i32);
+
+// Cost of moving one tile horizontally or vertically. Diagonal moves cost
+// DIAGONAL_COST (approximately sqrt(2) * STRAIGHT_COST).
+const STRAIGHT_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Movement {
+    // Can move through any non-solid tile, including diagonally (but not
+    // cutting across the corner of a solid tile).
+    Flying,
+
+    // Affected by gravity. Can walk on top of solid tiles, climb ladders,
+    // fall off ledges, and jump up to jump_height tiles up and
+    // jump_distance tiles across.
+    Walking {
+        jump_height: i32,
+        jump_distance: i32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathStatus {
+    // Hit the node limit before finishing. Call step again to continue.
+    Searching,
+
+    // Tiles to move through in order, not including the start, but including
+    // the goal (so this is empty if start and goal are the same).
+    Found(Vec<TilePos>),
+
+    NoPath,
+}
+
+// Searching can be spread over several frames by calling step with a limit
+// on the number of nodes to expand each time. Once the search finishes, the
+// path can be kept and followed until the goal changes.
+pub struct PathSearch {
+    goal: TilePos,
+    movement: Movement,
+
+    // Ordered by (estimated total cost, cost so far). Entries are not removed
+    // when a cheaper route to a node is found, so stale ones are skipped when
+    // popped.
+    open: BinaryHeap<Reverse<(i32, i32, TilePos)>>,
+    costs: HashMap<TilePos, i32>,
+    came_from: HashMap<TilePos, TilePos>,
+    status: PathStatus,
+}
+
+impl PathSearch {
+    pub fn new(start: TilePos, goal: TilePos, movement: Movement) -> PathSearch {
+        let mut search = PathSearch {
+            goal,
+            movement,
+            open: BinaryHeap::new(),
+            costs: HashMap::new(),
+            came_from: HashMap::new(),
+            status: PathStatus::Searching,
+        };
+
+        search.costs.insert(start, 0);
+        search
+            .open
+            .push(Reverse((search.estimate_cost(start), 0, start)));
+        search
+    }
+
+    pub fn goal(&self) -> TilePos {
+        self.goal
+    }
+
+    pub fn status(&self) -> &PathStatus {
+        &self.status
+    }
+
+    // Expand up to max_nodes nodes and return the status.
+    pub fn step(&mut self, tile_map: &tilemap::TileMap, max_nodes: usize) -> &PathStatus {
+        if self.status != PathStatus::Searching {
+            return &self.status;
+        }
+
+        let mut neighbors = Vec::new();
+        for _ in 0..max_nodes {
+            let Some(Reverse((_, cost, pos))) = self.open.pop() else {
+                self.status = PathStatus::NoPath;
+                break;
+            };
+
+            if cost > self.costs[&pos] {
+                continue;
+            }
+
+            if pos == self.goal {
+                self.status = PathStatus::Found(self.build_path());
+                break;
+            }
+
+            neighbors.clear();
+            match self.movement {
+                Movement::Flying => get_flying_neighbors(tile_map, pos, &mut neighbors),
+                Movement::Walking {
+                    jump_height,
+                    jump_distance,
+                } => {
+                    get_walking_neighbors(tile_map, pos, jump_height, jump_distance, &mut neighbors)
+                }
+            }
+
+            for &(next, move_cost) in &neighbors {
+                let next_cost = cost + move_cost;
+                if self
+                    .costs
+                    .get(&next)
+                    .is_none_or(|&old_cost| next_cost < old_cost)
+                {
+                    self.costs.insert(next, next_cost);
+                    self.came_from.insert(next, pos);
+                    self.open.push(Reverse((
+                        next_cost + self.estimate_cost(next),
+                        next_cost,
+                        next,
+                    )));
+                }
+            }
+        }
+
+        &self.status
+    }
+
+    // Heuristic. This never overestimates, so the path found is the
+    // cheapest one.
+    fn estimate_cost(&self, pos: TilePos) -> i32 {
+        let dx = (self.goal.0 - pos.0).abs();
+        let dy = (self.goal.1 - pos.1).abs();
+        match self.movement {
+            Movement::Flying => {
+                STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+            }
+            Movement::Walking { .. } => STRAIGHT_COST * (dx + dy),
+        }
+    }
+
+    fn build_path(&self) -> Vec<TilePos> {
+        let mut path = Vec::new();
+        let mut pos = self.goal;
+        while let Some(&prev) = self.came_from.get(&pos) {
+            path.push(pos);
+            pos = prev;
+        }
+
+        path.reverse();
+        path
+    }
+}
+
+// Convenience to run a whole search at once. Returns None if there is no
+// path, or it couldn't be found without expanding more than max_nodes nodes.
+pub fn find_path(
+    tile_map: &tilemap::TileMap,
+    start: TilePos,
+    goal: TilePos,
+    movement: Movement,
+    max_nodes: usize,
+) -> Option<Vec<TilePos>> {
+    let mut search = PathSearch::new(start, goal, movement);
+    match search.step(tile_map, max_nodes) {
+        PathStatus::Found(path) => Some(path.clone()),
+        _ => None,
+    }
+}
+
+fn is_open(tile_map: &tilemap::TileMap, (x, y): TilePos) -> bool {
+    tile_map.in_bounds(x, y) && !tile_map.is_solid_tile(x, y)
+}
+
+// A walker can stay in this tile without falling.
+fn is_standable(tile_map: &tilemap::TileMap, (x, y): TilePos) -> bool {
+    is_open(tile_map, (x, y))
+        && (tile_map.is_ladder_tile(x, y)
+            || tile_map.get_slope_tile(x, y).is_some()
+            || tile_map.is_solid_tile(x, y + 1)
+            || tile_map.is_one_way_tile(x, y + 1)
+            || tile_map.is_ladder_tile(x, y + 1))
+}
+
+fn get_flying_neighbors(
+    tile_map: &tilemap::TileMap,
+    (x, y): TilePos,
+    neighbors: &mut Vec<(TilePos, i32)>,
+) {
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx, dy) == (0, 0) || !is_open(tile_map, (x + dx, y + dy)) {
+                continue;
+            }
+
+            if dx == 0 || dy == 0 {
+                neighbors.push(((x + dx, y + dy), STRAIGHT_COST));
+            } else if is_open(tile_map, (x + dx, y)) && is_open(tile_map, (x, y + dy)) {
+                neighbors.push(((x + dx, y + dy), DIAGONAL_COST));
+            }
+        }
+    }
+}
+
+fn get_walking_neighbors(
+    tile_map: &tilemap::TileMap,
+    (x, y): TilePos,
+    jump_height: i32,
+    jump_distance: i32,
+    neighbors: &mut Vec<(TilePos, i32)>,
+) {
+    for dir in [-1, 1] {
+        // Walk sideways, or step off a ledge and fall until landing on
+        // something.
+        if is_open(tile_map, (x + dir, y)) {
+            let mut landing_y = y;
+            while is_open(tile_map, (x + dir, landing_y))
+                && !is_standable(tile_map, (x + dir, landing_y))
+            {
+                landing_y += 1;
+            }
+
+            if is_standable(tile_map, (x + dir, landing_y)) {
+                neighbors.push(((x + dir, landing_y), STRAIGHT_COST * (1 + landing_y - y)));
+            }
+        }
+
+        // Jump up and/or across. The walker goes straight up, then across,
+        // so both of those need to be clear. Walking to the adjacent tile on
+        // the same level is handled above.
+        for height in 0..=jump_height {
+            if height > 0 && !is_open(tile_map, (x, y - height)) {
+                break;
+            }
+
+            for distance in 1..=jump_distance {
+                let target = (x + dir * distance, y - height);
+                if !is_open(tile_map, target) {
+                    break;
+                }
+
+                if (height > 0 || distance > 1) && is_standable(tile_map, target) {
+                    neighbors.push((target, STRAIGHT_COST * (distance + height + 1)));
+                }
+            }
+        }
+    }
+
+    // Climb ladders
+    if tile_map.is_ladder_tile(x, y) && is_open(tile_map, (x, y - 1)) {
+        neighbors.push(((x, y - 1), STRAIGHT_COST));
+    }
+
+    if tile_map.is_ladder_tile(x, y + 1) {
+        neighbors.push(((x, y + 1), STRAIGHT_COST));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALKER: Movement = Movement::Walking {
+        jump_height: 1,
+        jump_distance: 2,
+    };
+
+    #[test]
+    fn test_flying_around_wall() {
+        let map = tilemap::create_map_from_text(&[
+            "......", //
+            "..#...", //
+            "..#...", //
+            "..#...", //
+        ]);
+        let path = find_path(&map, (0, 3), (4, 3), Movement::Flying, 1000).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                (1, 2),
+                (1, 1),
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (3, 1),
+                (3, 2),
+                (4, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flying_no_corner_cutting() {
+        let map = tilemap::create_map_from_text(&[
+            ".#", //
+            "..", //
+        ]);
+        let path = find_path(&map, (0, 0), (1, 1), Movement::Flying, 1000).unwrap();
+        assert_eq!(path, vec![(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn test_no_path() {
+        let map = tilemap::create_map_from_text(&[
+            "..#..", //
+            "..#..", //
+        ]);
+        assert_eq!(
+            find_path(&map, (0, 0), (4, 0), Movement::Flying, 1000),
+            None
+        );
+        assert_eq!(
+            find_path(&map, (0, 0), (0, 0), Movement::Flying, 1000),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_walking() {
+        // Walk right, climb the ladder, jump onto the wall, then drop down
+        // the gap on the other side.
+        let map = tilemap::create_map_from_text(&[
+            "...........", //
+            "...H..#....", //
+            "...H####.#.", //
+            "...H#......", //
+            "####.......", //
+            "###########", //
+        ]);
+        let path = find_path(&map, (0, 3), (10, 4), WALKER, 1000).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                (1, 3),
+                (2, 3),
+                (3, 3),
+                (3, 2),
+                (3, 1),
+                (4, 1),
+                (6, 0), // jump
+                (7, 1),
+                (8, 4), // fall
+                (9, 4),
+                (10, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walking_platforms_and_slopes() {
+        let map = tilemap::create_map_from_text(&[
+            "......", //
+            "..--..", //
+            "/.....", //
+            "######", //
+        ]);
+        assert!(is_standable(&map, (2, 0)));
+        assert!(is_standable(&map, (0, 2)));
+        assert!(!is_standable(&map, (2, 1)));
+
+        // Jump up through the platform
+        let high_jumper = Movement::Walking {
+            jump_height: 2,
+            jump_distance: 2,
+        };
+        assert_eq!(
+            find_path(&map, (0, 2), (3, 0), high_jumper, 1000),
+            Some(vec![(1, 2), (3, 0)])
+        );
+    }
+
+    #[test]
+    fn test_walking_jump_too_high() {
+        let map = tilemap::create_map_from_text(&[
+            "....", //
+            "..##", //
+            "..#.", //
+            "####", //
+        ]);
+        assert_eq!(find_path(&map, (0, 2), (3, 0), WALKER, 1000), None);
+
+        let high_jumper = Movement::Walking {
+            jump_height: 2,
+            jump_distance: 2,
+        };
+        assert_eq!(
+            find_path(&map, (0, 2), (3, 0), high_jumper, 1000),
+            Some(vec![(1, 2), (3, 0)])
+        );
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let map = tilemap::create_map_from_text(&[
+            "..........", //
+            "..........", //
+            "..........", //
+        ]);
+
+        // Each step is bounded, but it eventually finds the same path.
+        let mut search = PathSearch::new((0, 1), (9, 1), Movement::Flying);
+        let mut steps = 0;
+        while *search.step(&map, 2) == PathStatus::Searching {
+            steps += 1;
+        }
+
+        assert!(steps > 1);
+        assert_eq!(
+            *search.status(),
+            PathStatus::Found(find_path(&map, (0, 1), (9, 1), Movement::Flying, 1000).unwrap())
+        );
+        assert_eq!(find_path(&map, (0, 1), (9, 1), Movement::Flying, 2), None);
+    }
+}
@@ -0,0 +1,302 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//
+// CPU implementation of the same drawing operations the OpenGL backend
+// performs: textured triangles sampled from the atlas with bilinear
+// filtering, alpha blended into a framebuffer. This allows rendering
+// without a GPU or display.
+//
+
+use image::RgbaImage;
+
+// Vertices are snapped to a fixed point grid with this many fractional bits,
+// so coverage tests are exact and shared edges are never drawn twice.
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+
+// Matches the layout built by RenderContext::draw_quad:
+// x, y (OpenGL normalized coordinates), u, v
+const ATTR_ELEMS_PER_VERTEX: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    x: i64,
+    y: i64,
+    u: f32,
+    v: f32,
+}
+
+pub struct Rasterizer {
+    atlas: RgbaImage,
+    framebuffer: RgbaImage,
+}
+
+impl Rasterizer {
+    pub fn new(atlas: RgbaImage, width: u32, height: u32) -> Rasterizer {
+        Rasterizer {
+            atlas,
+            framebuffer: RgbaImage::new(width, height),
+        }
+    }
+
+    pub fn framebuffer(&self) -> &RgbaImage {
+        &self.framebuffer
+    }
+
+    // Clear the framebuffer and draw a list of triangles into it.
+    pub fn submit(&mut self, vertices: &[f32]) {
+        // Same as the glClearColor in RenderContext::new
+        for pixel in self.framebuffer.pixels_mut() {
+            *pixel = image::Rgba([255, 255, 255, 255]);
+        }
+
+        for triangle in vertices.chunks_exact(ATTR_ELEMS_PER_VERTEX * 3) {
+            let v0 = self.to_screen(&triangle[0..4]);
+            let v1 = self.to_screen(&triangle[4..8]);
+            let v2 = self.to_screen(&triangle[8..12]);
+            self.fill_triangle(v0, v1, v2);
+        }
+    }
+
+    // Convert from OpenGL coordinate space back to (fixed point) pixels.
+    fn to_screen(&self, attrs: &[f32]) -> Vertex {
+        let width = self.framebuffer.width() as f32;
+        let height = self.framebuffer.height() as f32;
+        let x = (attrs[0] + 1.0) * 0.5 * width;
+        let y = (1.0 - attrs[1]) * 0.5 * height;
+        Vertex {
+            x: (x * SUBPIXEL_ONE as f32).round() as i64,
+            y: (y * SUBPIXEL_ONE as f32).round() as i64,
+            u: attrs[2],
+            v: attrs[3],
+        }
+    }
+
+    fn fill_triangle(&mut self, v0: Vertex, mut v1: Vertex, mut v2: Vertex) {
+        let mut area = edge_function(&v0, &v1, v2.x, v2.y);
+        if area == 0 {
+            return;
+        }
+
+        // Normalize winding so points inside have positive edge values.
+        if area < 0 {
+            std::mem::swap(&mut v1, &mut v2);
+            area = -area;
+        }
+
+        let fb_width = self.framebuffer.width() as i64;
+        let fb_height = self.framebuffer.height() as i64;
+        let min_x = (v0.x.min(v1.x).min(v2.x) >> SUBPIXEL_BITS).max(0);
+        let max_x = (v0.x.max(v1.x).max(v2.x) >> SUBPIXEL_BITS).min(fb_width - 1);
+        let min_y = (v0.y.min(v1.y).min(v2.y) >> SUBPIXEL_BITS).max(0);
+        let max_y = (v0.y.max(v1.y).max(v2.y) >> SUBPIXEL_BITS).min(fb_height - 1);
+
+        let include_edge0 = is_tie_included(&v1, &v2);
+        let include_edge1 = is_tie_included(&v2, &v0);
+        let include_edge2 = is_tie_included(&v0, &v1);
+        let inv_area = 1.0 / area as f32;
+
+        for y in min_y..=max_y {
+            // Sample at pixel centers
+            let py = (y << SUBPIXEL_BITS) + SUBPIXEL_ONE / 2;
+            for x in min_x..=max_x {
+                let px = (x << SUBPIXEL_BITS) + SUBPIXEL_ONE / 2;
+                let w0 = edge_function(&v1, &v2, px, py);
+                let w1 = edge_function(&v2, &v0, px, py);
+                let w2 = edge_function(&v0, &v1, px, py);
+                if !is_inside(w0, include_edge0)
+                    || !is_inside(w1, include_edge1)
+                    || !is_inside(w2, include_edge2)
+                {
+                    continue;
+                }
+
+                let l0 = w0 as f32 * inv_area;
+                let l1 = w1 as f32 * inv_area;
+                let l2 = w2 as f32 * inv_area;
+                let u = l0 * v0.u + l1 * v1.u + l2 * v2.u;
+                let v = l0 * v0.v + l1 * v1.v + l2 * v2.v;
+                let color = self.sample(u, v);
+                self.blend(x as u32, y as u32, color);
+            }
+        }
+    }
+
+    // Bilinear filtering with clamp to edge, which is how the atlas texture is
+    // configured in gfx.rs.
+    fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        let x = u * self.atlas.width() as f32 - 0.5;
+        let y = v * self.atlas.height() as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let frac_x = x - x0;
+        let frac_y = y - y0;
+
+        let ul = self.texel(x0 as i64, y0 as i64);
+        let ur = self.texel(x0 as i64 + 1, y0 as i64);
+        let ll = self.texel(x0 as i64, y0 as i64 + 1);
+        let lr = self.texel(x0 as i64 + 1, y0 as i64 + 1);
+
+        let mut result = [0.0; 4];
+        for i in 0..4 {
+            let top = ul[i] + (ur[i] - ul[i]) * frac_x;
+            let bottom = ll[i] + (lr[i] - ll[i]) * frac_x;
+            result[i] = top + (bottom - top) * frac_y;
+        }
+
+        result
+    }
+
+    fn texel(&self, x: i64, y: i64) -> [f32; 4] {
+        let x = x.clamp(0, self.atlas.width() as i64 - 1) as u32;
+        let y = y.clamp(0, self.atlas.height() as i64 - 1) as u32;
+        let pixel = self.atlas.get_pixel(x, y);
+        pixel.0.map(|channel| channel as f32 / 255.0)
+    }
+
+    // Equivalent to glBlendFunc(GL_SRC_ALPHA, GL_ONE_MINUS_SRC_ALPHA)
+    fn blend(&mut self, x: u32, y: u32, src: [f32; 4]) {
+        let dest = self.framebuffer.get_pixel_mut(x, y);
+        let alpha = src[3];
+        for (dest_channel, src_value) in dest.0.iter_mut().zip(src) {
+            let dest_value = *dest_channel as f32 / 255.0;
+            let value = src_value * alpha + dest_value * (1.0 - alpha);
+            *dest_channel = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+// Twice the signed area of the triangle (a, b, p). This is positive when
+// p is on the inside of edge a->b.
+fn edge_function(a: &Vertex, b: &Vertex, px: i64, py: i64) -> i64 {
+    (b.x - a.x) * (py - a.y) - (b.y - a.y) * (px - a.x)
+}
+
+// When a pixel center lies exactly on an edge shared by two triangles, only
+// one of them should draw it, otherwise blending would be applied twice.
+// The shared edge runs in opposite directions in each, so picking based
+// on direction ensures exactly one includes it.
+fn is_tie_included(a: &Vertex, b: &Vertex) -> bool {
+    let dy = b.y - a.y;
+    dy > 0 || (dy == 0 && b.x > a.x)
+}
+
+fn is_inside(edge_value: i64, include_tie: bool) -> bool {
+    edge_value > 0 || (edge_value == 0 && include_tie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2 atlas, left column opaque red, right column half transparent blue.
+    fn make_atlas() -> RgbaImage {
+        let mut atlas = RgbaImage::new(2, 2);
+        for y in 0..2 {
+            atlas.put_pixel(0, y, image::Rgba([255, 0, 0, 255]));
+            atlas.put_pixel(1, y, image::Rgba([0, 0, 255, 128]));
+        }
+
+        atlas
+    }
+
+    // Build a quad in the same layout as RenderContext::draw_quad
+    fn push_quad(
+        vertices: &mut Vec<f32>,
+        size: (f32, f32),
+        rect: (f32, f32, f32, f32),
+        src: (f32, f32, f32, f32),
+    ) {
+        let to_ogl = |x: f32, y: f32| (x / size.0 * 2.0 - 1.0, 1.0 - y / size.1 * 2.0);
+        let p0 = to_ogl(rect.0, rect.1);
+        let p1 = to_ogl(rect.2, rect.1);
+        let p2 = to_ogl(rect.0, rect.3);
+        let p3 = to_ogl(rect.2, rect.3);
+        vertices.extend_from_slice(&[
+            p0.0, p0.1, src.0, src.1, p1.0, p1.1, src.2, src.1, p2.0, p2.1, src.0, src.3, p1.0,
+            p1.1, src.2, src.1, p3.0, p3.1, src.2, src.3, p2.0, p2.1, src.0, src.3,
+        ]);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut rasterizer = Rasterizer::new(make_atlas(), 16, 16);
+        let mut vertices = Vec::new();
+
+        // Sample only the center of the red texel, so filtering doesn't blend
+        // in the neighbor.
+        push_quad(
+            &mut vertices,
+            (16.0, 16.0),
+            (4.0, 2.0, 12.0, 10.0),
+            (0.25, 0.25, 0.25, 0.25),
+        );
+        rasterizer.submit(&vertices);
+
+        let framebuffer = rasterizer.framebuffer();
+        for y in 0..16 {
+            for x in 0..16 {
+                let expected = if (4..12).contains(&x) && (2..10).contains(&y) {
+                    [255, 0, 0, 255]
+                } else {
+                    [255, 255, 255, 255]
+                };
+                assert_eq!(framebuffer.get_pixel(x, y).0, expected, "at {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blend_once_on_diagonal() {
+        let mut rasterizer = Rasterizer::new(make_atlas(), 8, 8);
+        let mut vertices = Vec::new();
+        push_quad(
+            &mut vertices,
+            (8.0, 8.0),
+            (0.0, 0.0, 8.0, 8.0),
+            (0.75, 0.25, 0.75, 0.25),
+        );
+        rasterizer.submit(&vertices);
+
+        // The pixels on the diagonal between the two triangles must be the
+        // same as the rest, which they wouldn't if they were blended twice.
+        let expected = rasterizer.framebuffer().get_pixel(0, 0).0;
+        assert_eq!(expected, [127, 127, 255, 191]);
+        for pixel in rasterizer.framebuffer().pixels() {
+            assert_eq!(pixel.0, expected);
+        }
+    }
+
+    #[test]
+    fn test_winding() {
+        // Both windings should produce the same coverage.
+        let mut rasterizer = Rasterizer::new(make_atlas(), 8, 8);
+        let cw = [
+            -1.0, 1.0, 0.25, 0.25, 1.0, 1.0, 0.25, 0.25, -1.0, -1.0, 0.25, 0.25,
+        ];
+        rasterizer.submit(&cw);
+        let cw_result = rasterizer.framebuffer().clone();
+
+        let ccw = [
+            -1.0, 1.0, 0.25, 0.25, -1.0, -1.0, 0.25, 0.25, 1.0, 1.0, 0.25, 0.25,
+        ];
+        rasterizer.submit(&ccw);
+        assert_eq!(&cw_result, rasterizer.framebuffer());
+        assert_eq!(cw_result.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(cw_result.get_pixel(7, 7).0, [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,162 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//
+// Recording and playback of player input. Since the simulation runs at a
+// fixed time step and all randomness comes from the engine's seeded RNG,
+// replaying the same button states from the same seed reproduces a run
+// exactly.
+//
+// File format
+//    magic [u8; 4]  "RPLY"
+//    version: u32   REPLAY_VERSION
+//    seed: u64
+//    buttons: [u32; number of simulation steps]
+//
+
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RPLY";
+const HEADER_SIZE: usize = 16;
+
+// This must be changed whenever something that affects playback does, such as
+// the RNG algorithm (util::GameRng, currently ChaCha8), so old recordings are
+// rejected rather than silently playing back differently.
+const REPLAY_VERSION: u32 = 1;
+
+pub struct InputRecorder {
+    writer: std::io::BufWriter<std::fs::File>,
+}
+
+impl InputRecorder {
+    pub fn new(path: &Path, seed: u64) -> Result<InputRecorder, String> {
+        let file =
+            std::fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let mut writer = std::io::BufWriter::new(file);
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&REPLAY_VERSION.to_le_bytes());
+        header.extend_from_slice(&seed.to_le_bytes());
+        writer
+            .write_all(&header)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        Ok(InputRecorder { writer })
+    }
+
+    pub fn record(&mut self, buttons: u32) {
+        self.writer.write_all(&buttons.to_le_bytes()).unwrap();
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().unwrap();
+    }
+}
+
+pub struct InputPlayback {
+    pub seed: u64,
+    buttons: Vec<u32>,
+    next_index: usize,
+}
+
+impl InputPlayback {
+    pub fn new(path: &Path) -> Result<InputPlayback, String> {
+        let data = std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+            return Err(format!("{}: not a replay file", path.display()));
+        }
+
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if version != REPLAY_VERSION {
+            return Err(format!(
+                "{}: replay file version {} is not supported (expected {})",
+                path.display(),
+                version,
+                REPLAY_VERSION
+            ));
+        }
+
+        let seed = u64::from_le_bytes(data[8..HEADER_SIZE].try_into().unwrap());
+        let buttons = data[HEADER_SIZE..]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        Ok(InputPlayback {
+            seed,
+            buttons,
+            next_index: 0,
+        })
+    }
+
+    // Returns the button state for the next simulation step, or None if the
+    // recording has ended.
+    pub fn next_buttons(&mut self) -> Option<u32> {
+        let buttons = self.buttons.get(self.next_index).copied();
+        self.next_index += 1;
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("replay-test-{}.bin", std::process::id()));
+        let mut recorder = InputRecorder::new(&path, 0x123456789abcdef0).unwrap();
+        recorder.record(0x1);
+        recorder.record(0x24);
+        recorder.record(0);
+        recorder.flush();
+
+        let mut playback = InputPlayback::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(playback.seed, 0x123456789abcdef0);
+        assert_eq!(playback.next_buttons(), Some(0x1));
+        assert_eq!(playback.next_buttons(), Some(0x24));
+        assert_eq!(playback.next_buttons(), Some(0));
+        assert_eq!(playback.next_buttons(), None);
+    }
+
+    #[test]
+    fn test_wrong_version() {
+        let path = std::env::temp_dir().join(format!("replay-version-{}.bin", std::process::id()));
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&(REPLAY_VERSION + 1).to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        let result = InputPlayback::new(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let path = std::env::temp_dir().join(format!("replay-invalid-{}.bin", std::process::id()));
+        assert!(InputPlayback::new(&path).is_err());
+
+        // Truncated header
+        std::fs::write(&path, b"RPLY\x01\x00").unwrap();
+        let result = InputPlayback::new(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+
+        let dir_path = std::env::temp_dir().join(format!("replay-missing-{}", std::process::id()));
+        assert!(InputRecorder::new(&dir_path.join("replay.bin"), 0).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::GameEngine;
+
+// What the engine should do with the scene stack after a scene's update.
+pub enum SceneTransition {
+    None,
+
+    // Push a new scene on top of this one. This scene stays on the stack,
+    // but is not updated until the new one is popped.
+    Push(Box<dyn Scene>),
+
+    // Remove this scene, returning to the one below it. If this was the last
+    // scene, the run loop exits.
+    Pop,
+
+    // Remove this scene and put another in its place.
+    Replace(Box<dyn Scene>),
+
+    Quit,
+}
+
+// A scene is a distinct mode of the game (title screen, gameplay, pause
+// menu). The engine keeps a stack of them. Only the top one is updated each
+// frame.
+pub trait Scene {
+    // Called when the scene is pushed on the stack.
+    fn on_enter(&mut self, _eng: &mut GameEngine) {}
+
+    // Called when the scene is removed from the stack.
+    fn on_exit(&mut self, _eng: &mut GameEngine) {}
+
+    // Called once per simulation step. d_t is always engine::TIME_STEP.
+    fn update(&mut self, eng: &mut GameEngine, d_t: f32) -> SceneTransition;
+
+    // alpha is the fraction of a time step (0.0-1.0) that has elapsed since
+    // the last update, for interpolating motion.
+    fn draw(&self, eng: &mut GameEngine, alpha: f32);
+
+    // An overlay is drawn on top of the scene below it, rather than
+    // replacing it (e.g. a pause menu over gameplay).
+    fn is_overlay(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type EventLog = Rc<RefCell<Vec<String>>>;
+
+    // Runs a fixed script of transitions, one per update, and logs every
+    // callback.
+    struct TestScene {
+        name: &'static str,
+        log: EventLog,
+        script: Vec<SceneTransition>,
+        overlay: bool,
+
+        // Pushed the first time the scene is drawn.
+        push_on_draw: RefCell<Option<Box<dyn Scene>>>,
+    }
+
+    impl Scene for TestScene {
+        fn on_enter(&mut self, _eng: &mut GameEngine) {
+            self.log.borrow_mut().push(format!("{} enter", self.name));
+        }
+
+        fn on_exit(&mut self, _eng: &mut GameEngine) {
+            self.log.borrow_mut().push(format!("{} exit", self.name));
+        }
+
+        fn update(&mut self, _eng: &mut GameEngine, _d_t: f32) -> SceneTransition {
+            self.log.borrow_mut().push(format!("{} update", self.name));
+            if self.script.is_empty() {
+                SceneTransition::None
+            } else {
+                self.script.remove(0)
+            }
+        }
+
+        fn draw(&self, eng: &mut GameEngine, _alpha: f32) {
+            self.log.borrow_mut().push(format!("{} draw", self.name));
+            if let Some(scene) = self.push_on_draw.take() {
+                eng.push_scene(scene);
+            }
+        }
+
+        fn is_overlay(&self) -> bool {
+            self.overlay
+        }
+    }
+
+    #[test]
+    fn test_scene_stack() {
+        let log = EventLog::default();
+        let overlay = Box::new(TestScene {
+            name: "b",
+            log: log.clone(),
+            script: vec![SceneTransition::None, SceneTransition::Pop],
+            overlay: true,
+            push_on_draw: RefCell::default(),
+        });
+        let base = Box::new(TestScene {
+            name: "a",
+            log: log.clone(),
+            script: vec![SceneTransition::Push(overlay), SceneTransition::Quit],
+            overlay: false,
+            push_on_draw: RefCell::default(),
+        });
+
+        let mut eng = GameEngine::new_headless();
+        eng.run(base);
+
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "a enter", "a update", "b enter", "a draw", "b draw", "b update", "a draw",
+                "b draw", "b update", "b exit", "a draw", "a update", "a exit",
+            ]
+        );
+        assert_eq!(eng.render_context.frame_count(), 3);
+    }
+
+    #[test]
+    fn test_push_while_drawing() {
+        let log = EventLog::default();
+        let overlay = Box::new(TestScene {
+            name: "b",
+            log: log.clone(),
+            script: vec![SceneTransition::Pop],
+            overlay: true,
+            push_on_draw: RefCell::default(),
+        });
+        let base = Box::new(TestScene {
+            name: "a",
+            log: log.clone(),
+            script: vec![SceneTransition::None, SceneTransition::Quit],
+            overlay: false,
+            push_on_draw: RefCell::new(Some(overlay)),
+        });
+
+        let mut eng = GameEngine::new_headless();
+        eng.run(base);
+
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "a enter", "a update", "a draw", "b enter", "b update", "b exit", "a draw",
+                "a update", "a exit",
+            ]
+        );
+    }
+}
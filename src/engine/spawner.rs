@@ -0,0 +1,397 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Entities for objects in the map are only created when the object comes
+// within a margin of the visible area, and are removed again when they get
+// far enough away (the larger margin avoids repeatedly creating and removing
+// ones near the edge). When an entity is removed this way, the state it
+// saves (see Entity::save_state) is given back to the next entity created
+// for the object. Objects whose entities are destroyed (for example, a
+// balloon that was popped) are not created again. Transient entities that
+// don't belong to an object, such as arrows, are removed for good once they
+// are that far away.
+
+use crate::entity::{EntityId, EntityList};
+use crate::tilemap::MapObject;
+use crate::util;
+use crate::EntityCreateFn;
+use std::any::Any;
+use std::collections::HashMap;
+
+// What is remembered about an object whose entity was removed.
+pub enum SavedObject {
+    Removed,
+
+    // Where the entity was when it was removed, and what it returned from
+    // save_state.
+    State(util::Rect<i32>, Box<dyn Any>),
+}
+
+enum ObjectState {
+    Inactive(Option<(util::Rect<i32>, Box<dyn Any>)>),
+    Active(EntityId),
+    Removed,
+}
+
+#[derive(Default)]
+pub struct ObjectSpawner {
+    // One entry per object in the map, in the same order.
+    states: Vec<ObjectState>,
+}
+
+impl ObjectSpawner {
+    // Start tracking the objects for a newly loaded map. saved is what save
+    // returned when the player last left the map, by object index.
+    pub fn reset(&mut self, num_objects: usize, saved: HashMap<usize, SavedObject>) {
+        self.states = (0..num_objects)
+            .map(|_| ObjectState::Inactive(None))
+            .collect();
+        for (index, saved_object) in saved {
+            self.states[index] = match saved_object {
+                SavedObject::Removed => ObjectState::Removed,
+                SavedObject::State(rect, state) => ObjectState::Inactive(Some((rect, state))),
+            };
+        }
+    }
+
+    // Remember the state of every object, including ones that have entities
+    // now, so it can be restored by reset. This is called when leaving the
+    // map, before its entities are removed.
+    pub fn save(&mut self, entities: &EntityList) -> HashMap<usize, SavedObject> {
+        let mut saved = HashMap::new();
+        for (index, state) in std::mem::take(&mut self.states).into_iter().enumerate() {
+            let saved_object = match state {
+                ObjectState::Inactive(None) => None,
+                ObjectState::Inactive(Some((rect, state))) => Some(SavedObject::State(rect, state)),
+                ObjectState::Active(id) => match entities.get(id) {
+                    None => Some(SavedObject::Removed),
+                    Some(entity) => entity
+                        .save_state()
+                        .map(|state| SavedObject::State(entity.get_bounding_box(), state)),
+                },
+                ObjectState::Removed => Some(SavedObject::Removed),
+            };
+
+            if let Some(saved_object) = saved_object {
+                saved.insert(index, saved_object);
+            }
+        }
+
+        saved
+    }
+
+    // This should be called after dead entities have been removed, so it can
+    // tell they are gone. The player is never removed.
+    pub fn update(
+        &mut self,
+        objects: &[MapObject],
+        entity_fns: &HashMap<String, EntityCreateFn>,
+        entities: &mut EntityList,
+        player_id: Option<EntityId>,
+        visible_rect: &util::Rect<i32>,
+        margin: i32,
+    ) {
+        let spawn_rect = expand_rect(visible_rect, margin);
+        let despawn_rect = expand_rect(visible_rect, margin * 2);
+        for (state, object) in self.states.iter_mut().zip(objects) {
+            match state {
+                ObjectState::Inactive(saved) => {
+                    // If the entity moved before it was removed, it comes
+                    // back where it was rather than where the object is.
+                    let rect = saved.as_ref().map_or(&object.rect, |(rect, _)| rect);
+                    if rect.overlaps(&spawn_rect) {
+                        let create_fn = entity_fns
+                            .get(&object.type_name)
+                            .unwrap_or_else(|| panic!("unknown object type {}", object.type_name));
+                        let mut entity = create_fn(object);
+                        if let Some((_, saved_state)) = saved.take() {
+                            entity.restore_state(saved_state);
+                        }
+
+                        *state = ObjectState::Active(entities.add(entity));
+                    }
+                }
+                ObjectState::Active(id) => match entities.get(*id) {
+                    None => *state = ObjectState::Removed,
+                    Some(entity) => {
+                        if !entity.get_bounding_box().overlaps(&despawn_rect) {
+                            let saved = entity
+                                .save_state()
+                                .map(|saved_state| (entity.get_bounding_box(), saved_state));
+                            entities.remove(*id);
+                            *state = ObjectState::Inactive(saved);
+                        }
+                    }
+                },
+                ObjectState::Removed => {}
+            }
+        }
+
+        let far_away: Vec<EntityId> = entities
+            .ids()
+            .iter()
+            .zip(entities.iter())
+            .filter(|(id, entity)| {
+                Some(**id) != player_id
+                    && entity.is_transient()
+                    && !entity.get_bounding_box().overlaps(&despawn_rect)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in far_away {
+            entities.remove(id);
+        }
+    }
+}
+
+fn expand_rect(rect: &util::Rect<i32>, margin: i32) -> util::Rect<i32> {
+    util::Rect::<i32>::new(
+        rect.left - margin,
+        rect.top - margin,
+        rect.width + margin * 2,
+        rect.height + margin * 2,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{Contact, Entity, UpdateContext};
+    use crate::gfx;
+    use std::any::Any;
+
+    struct TestEntity {
+        rect: util::Rect<i32>,
+        live: bool,
+        transient: bool,
+        state: i32,
+    }
+
+    impl Entity for TestEntity {
+        fn update(&mut self, _ctx: &mut UpdateContext) {}
+
+        fn draw(&self, _context: &mut gfx::RenderContext, _alpha: f32) {}
+
+        fn save_state(&self) -> Option<Box<dyn Any>> {
+            Some(Box::new((self.rect, self.state)))
+        }
+
+        fn restore_state(&mut self, state: Box<dyn Any>) {
+            (self.rect, self.state) = *state.downcast().unwrap();
+        }
+
+        fn is_transient(&self) -> bool {
+            self.transient
+        }
+
+        fn is_live(&self) -> bool {
+            self.live
+        }
+
+        fn get_collision_class(&self) -> u32 {
+            0
+        }
+
+        fn get_collision_mask(&self) -> u32 {
+            0
+        }
+
+        fn get_bounding_box(&self) -> util::Rect<i32> {
+            self.rect
+        }
+
+        fn collide(&mut self, _contact: &Contact) {}
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn create_test_entity(object: &MapObject) -> Box<dyn Entity> {
+        create_test_entity_at(object.rect.left, object.rect.top)
+    }
+
+    fn create_test_entity_at(x: i32, y: i32) -> Box<dyn Entity> {
+        Box::new(TestEntity {
+            rect: util::Rect::<i32>::new(x, y, 10, 10),
+            live: true,
+            transient: false,
+            state: 0,
+        })
+    }
+
+    fn create_transient_entity_at(x: i32, y: i32) -> Box<dyn Entity> {
+        Box::new(TestEntity {
+            rect: util::Rect::<i32>::new(x, y, 10, 10),
+            live: true,
+            transient: true,
+            state: 0,
+        })
+    }
+
+    fn get_test_entity(entities: &mut EntityList, id: EntityId) -> &mut TestEntity {
+        let any: &mut dyn Any = entities.get_mut(id).unwrap().as_mut();
+        any.downcast_mut::<TestEntity>().unwrap()
+    }
+
+    fn create_entity_fns() -> HashMap<String, EntityCreateFn> {
+        let mut entity_fns: HashMap<String, EntityCreateFn> = HashMap::new();
+        entity_fns.insert(String::from("Test"), create_test_entity);
+        entity_fns
+    }
+
+    fn view_at(x: i32) -> util::Rect<i32> {
+        util::Rect::<i32>::new(x, 0, 100, 100)
+    }
+
+    #[test]
+    fn test_spawn_and_despawn() {
+        let objects = vec![
+            MapObject::new("Test", 50, 50),
+            MapObject::new("Test", 400, 50),
+        ];
+        let entity_fns = create_entity_fns();
+        let mut entities = EntityList::default();
+        let mut spawner = ObjectSpawner::default();
+        spawner.reset(objects.len(), HashMap::new());
+
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 100);
+        assert_eq!(entities.len(), 1);
+
+        // Both are within the spawn margin.
+        spawner.update(
+            &objects,
+            &entity_fns,
+            &mut entities,
+            None,
+            &view_at(250),
+            100,
+        );
+        assert_eq!(entities.len(), 2);
+
+        // The first is now outside the despawn margin.
+        spawner.update(
+            &objects,
+            &entity_fns,
+            &mut entities,
+            None,
+            &view_at(400),
+            100,
+        );
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities.iter().next().unwrap().get_bounding_box().left, 400);
+
+        // Coming back creates it again.
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 100);
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities.iter().next().unwrap().get_bounding_box().left, 50);
+    }
+
+    #[test]
+    fn test_removed_stay_removed() {
+        let objects = vec![
+            MapObject::new("Test", 50, 50),
+            MapObject::new("Test", 60, 50),
+        ];
+        let entity_fns = create_entity_fns();
+        let mut entities = EntityList::default();
+        let mut spawner = ObjectSpawner::default();
+        spawner.reset(objects.len(), HashMap::new());
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 50);
+        assert_eq!(entities.len(), 2);
+
+        let id = entities.ids()[1];
+        get_test_entity(&mut entities, id).live = false;
+        entities.remove_dead();
+
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 50);
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 50);
+        assert_eq!(entities.len(), 1);
+
+        // Also after leaving and coming back to the map.
+        let saved = spawner.save(&entities);
+        assert!(matches!(saved.get(&1), Some(SavedObject::Removed)));
+        let mut entities = EntityList::default();
+        spawner.reset(objects.len(), saved);
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 50);
+        assert_eq!(entities.len(), 1);
+    }
+
+    #[test]
+    fn test_state_restored() {
+        let objects = vec![MapObject::new("Test", 50, 50)];
+        let entity_fns = create_entity_fns();
+        let mut entities = EntityList::default();
+        let mut spawner = ObjectSpawner::default();
+        spawner.reset(objects.len(), HashMap::new());
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 100);
+        let id = entities.ids()[0];
+        let entity = get_test_entity(&mut entities, id);
+        entity.state = 5;
+        entity.rect.left = 80;
+
+        // Despawn and respawn it.
+        spawner.update(
+            &objects,
+            &entity_fns,
+            &mut entities,
+            None,
+            &view_at(400),
+            100,
+        );
+        assert!(entities.is_empty());
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 100);
+        let id = entities.ids()[0];
+        let entity = get_test_entity(&mut entities, id);
+        assert_eq!(entity.state, 5);
+        assert_eq!(entity.rect.left, 80);
+
+        // It is kept after leaving and coming back to the map too.
+        entity.state = 6;
+        let saved = spawner.save(&entities);
+        let mut entities = EntityList::default();
+        spawner.reset(objects.len(), saved);
+        spawner.update(&objects, &entity_fns, &mut entities, None, &view_at(0), 100);
+        let id = entities.ids()[0];
+        assert_eq!(get_test_entity(&mut entities, id).state, 6);
+    }
+
+    #[test]
+    fn test_despawn_transient_entities() {
+        let entity_fns = create_entity_fns();
+        let mut entities = EntityList::default();
+        let player_id = entities.add(create_transient_entity_at(500, 50));
+        let near_id = entities.add(create_transient_entity_at(150, 50));
+        let far_id = entities.add(create_transient_entity_at(400, 50));
+        let other_id = entities.add(create_test_entity_at(400, 50));
+        let mut spawner = ObjectSpawner::default();
+        spawner.reset(0, HashMap::new());
+        spawner.update(
+            &[],
+            &entity_fns,
+            &mut entities,
+            Some(player_id),
+            &view_at(0),
+            100,
+        );
+
+        assert!(entities.contains(player_id));
+        assert!(entities.contains(near_id));
+        assert!(!entities.contains(far_id));
+
+        // Entities that aren't transient stay until they die.
+        assert!(entities.contains(other_id));
+    }
+}
@@ -16,22 +16,260 @@
 
 use crate::gfx;
 use crate::util;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 pub const TILE_SIZE: i32 = 64;
 pub const TILE_SIZE_F: f32 = TILE_SIZE as f32;
 
+// Tile flags. Must match build_assets.rs
 const FLAG_SOLID: u8 = 1;
 const FLAG_LADDER: u8 = 2;
+const FLAG_WATER: u8 = 4;
+const FLAG_ONE_WAY: u8 = 8;
+const FLAG_BREAKABLE: u8 = 0x80;
 
+// The slope type is stored in these bits of the tile flags (0 is not a
+// slope, otherwise it is 1 + the index in SLOPES). Must match build_assets.rs
+const SLOPE_SHIFT: u8 = 4;
+const SLOPE_MASK: u8 = 0x70;
+
+// Layer flags. These must match build_assets.rs
+const LAYER_COLLISION: u32 = 1;
+const LAYER_FOREGROUND: u32 = 2;
+
+// Per-cell flip flags (also must match build_assets.rs). These are applied
+// in the same order as Tiled: diagonal (which swaps the x and y axes) first,
+// then horizontal, then vertical. Combinations of these give all four
+// rotations and their mirror images.
+const FLIP_HORIZONTAL: u8 = 1;
+const FLIP_VERTICAL: u8 = 2;
+const FLIP_DIAGONAL: u8 = 4;
+
+// Custom property types for objects (must match build_assets.rs)
+const PROPERTY_BOOL: u8 = 0;
+const PROPERTY_INT: u8 = 1;
+const PROPERTY_FLOAT: u8 = 2;
+const PROPERTY_STRING: u8 = 3;
+
+// Object types that are handled by the engine rather than creating entities.
+// Warps are triggers that move the player to a spawn point in another map
+// (see GameEngine::change_map).
+pub const TRIGGER_TYPE: &str = "Trigger";
+pub const WARP_TYPE: &str = "Warp";
+pub const SPAWN_TYPE: &str = "Spawn";
+
+// Sloped tiles are not solid. Instead, the player stands on the surface,
+// which is a straight line across the tile. 22.5 degree slopes take two tiles
+// to rise the height of one, so there is a low half and a high half.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slope {
+    Rise45, // Up to the right
+    Fall45, // Down to the right
+    Rise22Low,
+    Rise22High,
+    Fall22High,
+    Fall22Low,
+}
+
+const SLOPES: [Slope; 6] = [
+    Slope::Rise45,
+    Slope::Fall45,
+    Slope::Rise22Low,
+    Slope::Rise22High,
+    Slope::Fall22High,
+    Slope::Fall22Low,
+];
+
+impl Slope {
+    fn from_flags(flags: u8) -> Option<Slope> {
+        match (flags & SLOPE_MASK) >> SLOPE_SHIFT {
+            0 => None,
+            index => Some(SLOPES[index as usize - 1]),
+        }
+    }
+
+    // Height of the surface above the bottom of the tile, x pixels from the
+    // left edge of the tile.
+    pub fn height_at(self, x: f32) -> f32 {
+        let half = TILE_SIZE_F / 2.0;
+        let (left, right) = match self {
+            Slope::Rise45 => (0.0, TILE_SIZE_F),
+            Slope::Fall45 => (TILE_SIZE_F, 0.0),
+            Slope::Rise22Low => (0.0, half),
+            Slope::Rise22High => (half, TILE_SIZE_F),
+            Slope::Fall22High => (TILE_SIZE_F, half),
+            Slope::Fall22Low => (half, 0.0),
+        };
+
+        util::lerp(left, right, (x / TILE_SIZE_F).clamp(0.0, 1.0))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+// An object placed in the map with Tiled. type_name selects which function
+// creates the entity (see GameEngine::register_entity), and the rest is
+// passed to it, so entities can be customized from the editor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapObject {
+    pub type_name: String,
+    pub name: String,
+    pub rect: util::Rect<i32>, // In pixels
+    pub properties: HashMap<String, PropertyValue>,
+}
+
+impl MapObject {
+    // An object with no size, name, or properties.
+    pub fn new(type_name: &str, x: i32, y: i32) -> MapObject {
+        MapObject {
+            type_name: type_name.to_string(),
+            name: String::new(),
+            rect: util::Rect::<i32>::new(x, y, 0, 0),
+            properties: HashMap::new(),
+        }
+    }
+
+    // These return None if the property is missing or is a different type.
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.properties.get(name)? {
+            PropertyValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        match self.properties.get(name)? {
+            PropertyValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // Ints are converted, since it's easy to leave the decimal point off.
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.properties.get(name)? {
+            PropertyValue::Float(value) => Some(*value),
+            PropertyValue::Int(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.properties.get(name)? {
+            PropertyValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// A change to one cell of the collision layer (see set_tile). x and y are
+// in tiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileEdit {
+    pub x: i32,
+    pub y: i32,
+    pub tile: u16,
+}
+
+// Which sides of a rectangle ran into solid tiles in move_rect.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct TileCollision {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl TileCollision {
+    pub fn any(&self) -> bool {
+        self.left || self.right || self.top || self.bottom
+    }
+}
+
+// Side of a tile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileFace {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    // Tile coordinates (not pixels) of the solid tile that was hit.
+    pub tile_x: i32,
+    pub tile_y: i32,
+
+    // Pixel location where the ray entered the tile.
+    pub point: (f32, f32),
+    pub face: TileFace,
+}
+
+// Cycles the image shown for a tile (every cell containing that tile
+// animates in sync).
+struct TileAnimation {
+    tile_index: usize,
+
+    // (index of image in atlas_coords, duration in seconds)
+    frames: Vec<(usize, f32)>,
+    total_duration: f32,
+
+    // Time since the start of the current cycle. This is kept wrapped
+    // (rather than total time) so it doesn't lose precision as the game
+    // runs.
+    time: f32,
+}
+
+pub struct TileLayer {
+    pub name: String,
+    tiles: Vec<u16>,
+    flips: Vec<u8>,
+
+    // How fast this layer scrolls relative to the camera. Background layers
+    // that are less than 1 appear further away.
+    pub parallax_x: f32,
+    pub parallax_y: f32,
+
+    // Foreground layers are drawn on top of entities.
+    pub is_foreground: bool,
+}
+
+// All layers share the same width and height. Layers are drawn in order,
+// with the later ones on top.
 #[derive(Default)]
 pub struct TileMap {
     pub width: i32,
     pub height: i32,
-    tiles: Vec<u8>,
+    pub layers: Vec<TileLayer>,
+
+    // Index in layers of the one that get_flags, is_solid, etc. check.
+    collision_layer: usize,
     tile_flags: Vec<u8>,
     atlas_coords: Vec<gfx::SpriteInfo>,
-    pub objects: Vec<(String, i32, i32)>,
+    animations: Vec<TileAnimation>,
+
+    // For each tile, which entry in atlas_coords to draw now. This is the
+    // same as the tile index unless it is animated.
+    current_images: Vec<usize>,
+    pub objects: Vec<MapObject>,
+
+    // Triggers and warps. These are checked against the player by the engine
+    // rather than creating entities (see trigger.rs).
+    pub triggers: Vec<MapObject>,
+    pub spawn_points: Vec<MapObject>,
+
+    // Tiles changed by set_tile since the map was loaded, by (x, y), and the
+    // area (in tiles) changed since the last call to take_dirty_region.
+    modified_tiles: BTreeMap<(i32, i32), u16>,
+    dirty_region: Option<util::Rect<i32>>,
+
     pub player_start_x: i32,
     pub player_start_y: i32,
 }
@@ -70,36 +308,148 @@ impl TileMap {
         let mut tile_flags = vec![0; num_tiles];
         reader.read_slice(&mut tile_flags[..]);
 
-        // Read tile data
-        let mut tiles = vec![0; (width * height) as usize];
-        reader.read_slice(&mut tiles[..]);
+        let num_animations = reader.read_u32() as usize;
+        let mut animations = Vec::new();
+        for _ in 0..num_animations {
+            let tile_index = reader.read_u32() as usize;
+            let num_frames = reader.read_u32() as usize;
+            let frames: Vec<(usize, f32)> = (0..num_frames)
+                .map(|_| {
+                    let image_index = reader.read_u32() as usize;
+                    let duration_ms = reader.read_u32();
+                    (image_index, duration_ms as f32 / 1000.0)
+                })
+                .collect();
+            let total_duration = frames.iter().map(|(_, duration)| duration).sum();
+            animations.push(TileAnimation {
+                tile_index,
+                frames,
+                total_duration,
+                time: 0.0,
+            });
+        }
+
+        let num_layers = reader.read_u32() as usize;
+        let mut layers = Vec::new();
+        let mut collision_layer = None;
+        for index in 0..num_layers {
+            let name = Self::read_name(&mut reader);
+            let flags = reader.read_u32();
+            let parallax_x = reader.read_f32();
+            let parallax_y = reader.read_f32();
+            let tiles = (0..width * height).map(|_| reader.read_u16()).collect();
+            let mut flips = vec![0; (width * height) as usize];
+            reader.read_slice(&mut flips[..]);
+
+            if (flags & LAYER_COLLISION) != 0 {
+                collision_layer = Some(index);
+            }
 
-        // Read object locations.
+            layers.push(TileLayer {
+                name,
+                tiles,
+                flips,
+                parallax_x,
+                parallax_y,
+                is_foreground: (flags & LAYER_FOREGROUND) != 0,
+            });
+        }
+
+        // Read objects.
         let num_objects = reader.read_u32() as usize;
-        let mut objects: Vec<(String, i32, i32)> = Vec::new();
+        let mut objects: Vec<MapObject> = Vec::new();
         for _ in 0..num_objects {
-            let mut name_buf = [0u8; 32];
-            reader.read_slice(&mut name_buf);
-            let pos = name_buf.iter().position(|&x| x == 0).unwrap();
-            let name = String::from_utf8_lossy(&name_buf[..pos]).to_string();
+            let type_name = Self::read_name(&mut reader);
+            let name = Self::read_name(&mut reader);
             let x = reader.read_i32();
             let y = reader.read_i32();
+            let width = reader.read_i32();
+            let height = reader.read_i32();
+            let num_properties = reader.read_u32();
+            let mut properties = HashMap::new();
+            for _ in 0..num_properties {
+                let name = Self::read_name(&mut reader);
+                let value = match reader.read_u8() {
+                    PROPERTY_BOOL => PropertyValue::Bool(reader.read_u8() != 0),
+                    PROPERTY_INT => PropertyValue::Int(reader.read_i32()),
+                    PROPERTY_FLOAT => PropertyValue::Float(reader.read_f32()),
+                    PROPERTY_STRING => {
+                        let mut value = vec![0u8; reader.read_u32() as usize];
+                        reader.read_slice(&mut value);
+                        PropertyValue::String(String::from_utf8_lossy(&value).to_string())
+                    }
+                    property_type => panic!("unknown property type {}", property_type),
+                };
+
+                properties.insert(name, value);
+            }
 
-            objects.push((name, x, y));
+            objects.push(MapObject {
+                type_name,
+                name,
+                rect: util::Rect::<i32>::new(x, y, width, height),
+                properties,
+            });
+        }
+
+        let mut entity_objects = Vec::new();
+        let mut triggers = Vec::new();
+        let mut spawn_points = Vec::new();
+        for object in objects {
+            match object.type_name.as_str() {
+                TRIGGER_TYPE | WARP_TYPE => triggers.push(object),
+                SPAWN_TYPE => spawn_points.push(object),
+                _ => entity_objects.push(object),
+            }
         }
 
         TileMap {
-            tiles,
+            layers,
+            collision_layer: collision_layer.expect("No collision layer"),
             tile_flags,
             atlas_coords,
+            animations,
+            current_images: (0..num_tiles).collect(),
             width,
             height,
-            objects,
+            objects: entity_objects,
+            triggers,
+            spawn_points,
+            modified_tiles: BTreeMap::new(),
+            dirty_region: None,
             player_start_x,
             player_start_y,
         }
     }
 
+    // Read a zero padded, 32 byte string.
+    fn read_name(reader: &mut util::StructuredFileReader) -> String {
+        let mut name_buf = [0u8; 32];
+        reader.read_slice(&mut name_buf);
+        let pos = name_buf.iter().position(|&x| x == 0).unwrap();
+        String::from_utf8_lossy(&name_buf[..pos]).to_string()
+    }
+
+    // Advance tile animations.
+    pub fn update(&mut self, d_t: f32) {
+        for animation in &mut self.animations {
+            if animation.total_duration <= 0.0 {
+                continue;
+            }
+
+            animation.time = (animation.time + d_t) % animation.total_duration;
+            let mut time = animation.time;
+            for &(image_index, duration) in &animation.frames {
+                if time < duration {
+                    self.current_images[animation.tile_index] = image_index;
+                    break;
+                }
+
+                time -= duration;
+            }
+        }
+    }
+
     pub fn is_solid(&self, x: i32, y: i32) -> bool {
         (self.get_flags(x, y) & FLAG_SOLID) != 0
     }
@@ -108,12 +458,347 @@ impl TileMap {
         (self.get_flags(x, y) & FLAG_LADDER) != 0
     }
 
+    pub fn is_water(&self, x: i32, y: i32) -> bool {
+        (self.get_flags(x, y) & FLAG_WATER) != 0
+    }
+
+    // One way platforms can be jumped through from below, but are solid when
+    // landing on them from above.
+    pub fn is_one_way(&self, x: i32, y: i32) -> bool {
+        (self.get_flags(x, y) & FLAG_ONE_WAY) != 0
+    }
+
+    // True if the point is in a sloped tile, below the surface.
+    pub fn is_inside_slope(&self, x: f32, y: f32) -> bool {
+        let col = (x / TILE_SIZE_F).floor() as i32;
+        let row = (y / TILE_SIZE_F).floor() as i32;
+        match self.get_slope_tile(col, row) {
+            Some(slope) => y >= Self::slope_surface(slope, col, row, x),
+            None => false,
+        }
+    }
+
+    // Find the highest floor at x between top and bottom (in pixels) and
+    // return its y coordinate. This is either the top of a solid tile or the
+    // surface of a slope.
+    pub fn find_floor(&self, x: f32, top: f32, bottom: f32) -> Option<f32> {
+        let col = (x / TILE_SIZE_F).floor() as i32;
+        let (top_row, bottom_row) = Self::tile_span(top, bottom);
+        (top_row..=bottom_row).find_map(|row| {
+            let surface = if self.is_solid_tile(col, row) {
+                (row * TILE_SIZE) as f32
+            } else {
+                Self::slope_surface(self.get_slope_tile(col, row)?, col, row, x)
+            };
+
+            (surface >= top && surface <= bottom).then_some(surface)
+        })
+    }
+
+    fn slope_surface(slope: Slope, col: i32, row: i32, x: f32) -> f32 {
+        ((row + 1) * TILE_SIZE) as f32 - slope.height_at(x - (col * TILE_SIZE) as f32)
+    }
+
+    // Move rect by (dx, dy) (usually velocity * d_t), stopping at any solid
+    // tiles in the way. This moves horizontally then vertically, checking
+    // every tile the leading edge crosses, so fast moving objects can't pass
+    // through walls. Tiles the rect already overlaps are ignored, so
+    // something that ends up inside a wall can still get out. One way
+    // platforms only block moving down onto them from above. Returns the
+    // new rect and which sides were blocked.
+    pub fn move_rect(
+        &self,
+        rect: &util::Rect<f32>,
+        delta: (f32, f32),
+    ) -> (util::Rect<f32>, TileCollision) {
+        self.sweep_rect(rect, delta, true)
+    }
+
+    // Same as move_rect, but falls through one way platforms.
+    pub fn move_rect_through_platforms(
+        &self,
+        rect: &util::Rect<f32>,
+        delta: (f32, f32),
+    ) -> (util::Rect<f32>, TileCollision) {
+        self.sweep_rect(rect, delta, false)
+    }
+
+    fn sweep_rect(
+        &self,
+        rect: &util::Rect<f32>,
+        (dx, dy): (f32, f32),
+        land_on_platforms: bool,
+    ) -> (util::Rect<f32>, TileCollision) {
+        let mut result = *rect;
+        let mut collision = TileCollision::default();
+
+        if dx != 0.0 {
+            let (top_row, bottom_row) = Self::tile_span(result.top, result.bottom());
+            let is_blocked =
+                |col: i32| (top_row..=bottom_row).any(|row| self.is_solid_tile(col, row));
+            if dx > 0.0 {
+                result.left += dx;
+                let start_col = (rect.right() / TILE_SIZE_F).ceil() as i32;
+                let end_col = (result.right() / TILE_SIZE_F).ceil() as i32 - 1;
+                if let Some(col) = (start_col..=end_col).find(|&col| is_blocked(col)) {
+                    result.left = (col * TILE_SIZE) as f32 - result.width;
+                    collision.right = true;
+                }
+            } else {
+                result.left += dx;
+                let start_col = (rect.left / TILE_SIZE_F).floor() as i32 - 1;
+                let end_col = (result.left / TILE_SIZE_F).floor() as i32;
+                if let Some(col) = (end_col..=start_col).rev().find(|&col| is_blocked(col)) {
+                    result.left = ((col + 1) * TILE_SIZE) as f32;
+                    collision.left = true;
+                }
+            }
+        }
+
+        if dy != 0.0 {
+            let (left_col, right_col) = Self::tile_span(result.left, result.right());
+            let is_blocked =
+                |row: i32| (left_col..=right_col).any(|col| self.is_solid_tile(col, row));
+            if dy > 0.0 {
+                // Rows that start below the rect's original bottom edge, so
+                // this only lands on platforms that were below it.
+                let is_floor = |row: i32| {
+                    is_blocked(row)
+                        || (land_on_platforms
+                            && (left_col..=right_col).any(|col| self.is_one_way_tile(col, row)))
+                };
+                result.top += dy;
+                let start_row = (rect.bottom() / TILE_SIZE_F).ceil() as i32;
+                let end_row = (result.bottom() / TILE_SIZE_F).ceil() as i32 - 1;
+                if let Some(row) = (start_row..=end_row).find(|&row| is_floor(row)) {
+                    result.top = (row * TILE_SIZE) as f32 - result.height;
+                    collision.bottom = true;
+                }
+            } else {
+                result.top += dy;
+                let start_row = (rect.top / TILE_SIZE_F).floor() as i32 - 1;
+                let end_row = (result.top / TILE_SIZE_F).floor() as i32;
+                if let Some(row) = (end_row..=start_row).rev().find(|&row| is_blocked(row)) {
+                    result.top = ((row + 1) * TILE_SIZE) as f32;
+                    collision.top = true;
+                }
+            }
+        }
+
+        (result, collision)
+    }
+
+    // Walk the tiles along the line from start to end (in pixels), one at a
+    // time in order (a DDA traversal), and return the first solid one. The
+    // tile containing start is skipped, so a ray cast from inside a wall
+    // will get out.
+    pub fn raycast(&self, start: (f32, f32), end: (f32, f32)) -> Option<RaycastHit> {
+        let dx = end.0 - start.0;
+        let dy = end.1 - start.1;
+        let mut tile_x = (start.0 / TILE_SIZE_F).floor() as i32;
+        let mut tile_y = (start.1 / TILE_SIZE_F).floor() as i32;
+
+        // t is the fraction of the way along the line (0-1). t_max_* is the
+        // value of t where the ray crosses into the next column/row, and
+        // t_delta_* is how much t changes to cross a whole tile.
+        let (step_x, mut t_max_x, t_delta_x) = Self::dda_axis(start.0, dx, tile_x);
+        let (step_y, mut t_max_y, t_delta_y) = Self::dda_axis(start.1, dy, tile_y);
+        loop {
+            let (t, face) = if t_max_x < t_max_y {
+                tile_x += step_x;
+                let t = t_max_x;
+                t_max_x += t_delta_x;
+                (
+                    t,
+                    if step_x > 0 {
+                        TileFace::Left
+                    } else {
+                        TileFace::Right
+                    },
+                )
+            } else {
+                tile_y += step_y;
+                let t = t_max_y;
+                t_max_y += t_delta_y;
+                (
+                    t,
+                    if step_y > 0 {
+                        TileFace::Top
+                    } else {
+                        TileFace::Bottom
+                    },
+                )
+            };
+
+            if t > 1.0 {
+                return None;
+            }
+
+            if self.is_solid_tile(tile_x, tile_y) {
+                return Some(RaycastHit {
+                    tile_x,
+                    tile_y,
+                    point: (start.0 + dx * t, start.1 + dy * t),
+                    face,
+                });
+            }
+        }
+    }
+
+    // True if there are no solid tiles between the two points.
+    pub fn line_of_sight(&self, a: (f32, f32), b: (f32, f32)) -> bool {
+        self.raycast(a, b).is_none()
+    }
+
+    // Returns (step, t_max, t_delta) for one axis of raycast.
+    fn dda_axis(start: f32, delta: f32, tile: i32) -> (i32, f32, f32) {
+        if delta > 0.0 {
+            (
+                1,
+                ((tile + 1) as f32 * TILE_SIZE_F - start) / delta,
+                TILE_SIZE_F / delta,
+            )
+        } else if delta < 0.0 {
+            (
+                -1,
+                (tile as f32 * TILE_SIZE_F - start) / delta,
+                -TILE_SIZE_F / delta,
+            )
+        } else {
+            (0, f32::INFINITY, f32::INFINITY)
+        }
+    }
+
+    // Range of tile rows or columns (inclusive) covered by the span
+    // start..end in pixels.
+    fn tile_span(start: f32, end: f32) -> (i32, i32) {
+        let first = (start / TILE_SIZE_F).floor() as i32;
+        let last = (end / TILE_SIZE_F).ceil() as i32 - 1;
+        (first, last.max(first))
+    }
+
     pub fn get_flags(&self, x: i32, y: i32) -> u8 {
-        if x < 0 || y < 0 || x >= self.width * TILE_SIZE || y >= self.height * TILE_SIZE {
+        if x < 0 || y < 0 {
             return 0;
         }
 
-        let tile_num = self.tiles[((y / TILE_SIZE) * self.width + (x / TILE_SIZE)) as usize];
+        self.get_tile_flags(x / TILE_SIZE, y / TILE_SIZE)
+    }
+
+    // The *_tile functions below take tile coordinates rather than pixel
+    // coordinates.
+    pub fn in_bounds(&self, col: i32, row: i32) -> bool {
+        col >= 0 && row >= 0 && col < self.width && row < self.height
+    }
+
+    pub fn is_solid_tile(&self, col: i32, row: i32) -> bool {
+        (self.get_tile_flags(col, row) & FLAG_SOLID) != 0
+    }
+
+    pub fn is_ladder_tile(&self, col: i32, row: i32) -> bool {
+        (self.get_tile_flags(col, row) & FLAG_LADDER) != 0
+    }
+
+    pub fn is_water_tile(&self, col: i32, row: i32) -> bool {
+        (self.get_tile_flags(col, row) & FLAG_WATER) != 0
+    }
+
+    pub fn is_one_way_tile(&self, col: i32, row: i32) -> bool {
+        (self.get_tile_flags(col, row) & FLAG_ONE_WAY) != 0
+    }
+
+    pub fn get_slope_tile(&self, col: i32, row: i32) -> Option<Slope> {
+        Slope::from_flags(self.get_tile_flags(col, row))
+    }
+
+    // Breakable tiles can be destroyed by heavy arrows.
+    pub fn is_breakable_tile(&self, col: i32, row: i32) -> bool {
+        (self.get_tile_flags(col, row) & FLAG_BREAKABLE) != 0
+    }
+
+    // Tile number in the collision layer, using the same numbering as the
+    // map file (0 is empty, otherwise 1 + the index of the tile).
+    pub fn get_tile(&self, col: i32, row: i32) -> u16 {
+        if !self.in_bounds(col, row) {
+            return 0;
+        }
+
+        self.layers[self.collision_layer].tiles[(row * self.width + col) as usize]
+    }
+
+    // Change a tile in the collision layer (the tile number is the same as
+    // get_tile). Changes are recorded so they can be saved with
+    // get_modifications and applied again later, e.g. when the map is
+    // reloaded. Locations outside the map are ignored (so, for example, an
+    // explosion at the edge doesn't need to check), as are tile numbers that
+    // aren't in the tileset, and false is returned.
+    pub fn set_tile(&mut self, col: i32, row: i32, tile: u16) -> bool {
+        if !self.in_bounds(col, row) || tile as usize > self.tile_flags.len() {
+            return false;
+        }
+
+        let index = (row * self.width + col) as usize;
+        let layer = &mut self.layers[self.collision_layer];
+        layer.tiles[index] = tile;
+        layer.flips[index] = 0;
+        self.modified_tiles.insert((col, row), tile);
+
+        self.dirty_region = Some(match self.dirty_region {
+            None => util::Rect::<i32>::new(col, row, 1, 1),
+            Some(region) => {
+                let left = region.left.min(col);
+                let top = region.top.min(row);
+                util::Rect::<i32>::new(
+                    left,
+                    top,
+                    region.right().max(col + 1) - left,
+                    region.bottom().max(row + 1) - top,
+                )
+            }
+        });
+
+        true
+    }
+
+    // Returns the area (in tiles) that has changed since the last call, if
+    // any. The engine uses this to notify entities (Entity::tiles_changed).
+    pub fn take_dirty_region(&mut self) -> Option<util::Rect<i32>> {
+        self.dirty_region.take()
+    }
+
+    // All tiles that have been changed by set_tile, in a consistent order.
+    pub fn get_modifications(&self) -> Vec<TileEdit> {
+        self.modified_tiles
+            .iter()
+            .map(|(&(x, y), &tile)| TileEdit { x, y, tile })
+            .collect()
+    }
+
+    pub fn restore_modifications(&mut self, edits: &[TileEdit]) {
+        for edit in edits {
+            self.set_tile(edit.x, edit.y, edit.tile);
+        }
+    }
+
+    // Returns the location of the named spawn point, or the player start if
+    // name is empty.
+    pub fn find_spawn_point(&self, name: &str) -> Option<(i32, i32)> {
+        if name.is_empty() {
+            return Some((self.player_start_x, self.player_start_y));
+        }
+
+        self.spawn_points
+            .iter()
+            .find(|spawn| spawn.name == name)
+            .map(|spawn| (spawn.rect.left, spawn.rect.top))
+    }
+
+    pub fn get_tile_flags(&self, col: i32, row: i32) -> u8 {
+        if !self.in_bounds(col, row) {
+            return 0;
+        }
+
+        let tile_num = self.get_tile(col, row);
         if tile_num == 0 {
             return 0;
         }
@@ -121,30 +806,605 @@ impl TileMap {
         self.tile_flags[(tile_num - 1) as usize]
     }
 
-    pub fn draw(&self, context: &mut gfx::RenderContext, visible_rect: &util::Rect<i32>) {
-        let left_tile = visible_rect.left / TILE_SIZE;
-        let right_tile = std::cmp::min(
-            (visible_rect.right() + TILE_SIZE - 1) / TILE_SIZE,
-            self.width,
-        );
-        let top_tile = visible_rect.top / TILE_SIZE;
+    // Draw the layers that go behind entities. visible_rect is the part of
+    // the map the camera is looking at, and the context's offset should
+    // already be set to its top left corner.
+    pub fn draw_background(
+        &self,
+        context: &mut gfx::RenderContext,
+        visible_rect: &util::Rect<i32>,
+    ) {
+        for layer in self.layers.iter().filter(|layer| !layer.is_foreground) {
+            self.draw_layer(context, layer, visible_rect);
+        }
+    }
+
+    // Draw the layers that go in front of entities.
+    pub fn draw_foreground(
+        &self,
+        context: &mut gfx::RenderContext,
+        visible_rect: &util::Rect<i32>,
+    ) {
+        for layer in self.layers.iter().filter(|layer| layer.is_foreground) {
+            self.draw_layer(context, layer, visible_rect);
+        }
+    }
+
+    // Returns the part of the layer that is visible, which is different
+    // from the camera's visible_rect if the layer has parallax.
+    fn get_layer_view(layer: &TileLayer, visible_rect: &util::Rect<i32>) -> util::Rect<i32> {
+        util::Rect::<i32>::new(
+            (visible_rect.left as f32 * layer.parallax_x) as i32,
+            (visible_rect.top as f32 * layer.parallax_y) as i32,
+            visible_rect.width,
+            visible_rect.height,
+        )
+    }
+
+    // position is in screen coordinates.
+    fn draw_flipped_tile(
+        context: &mut gfx::RenderContext,
+        position: (i32, i32),
+        sprite: &gfx::SpriteInfo,
+        flips: u8,
+    ) {
+        let (mut src_left, mut src_top, mut src_right, mut src_bottom, ..) = *sprite;
+
+        // After a diagonal flip, the texture's x axis runs vertically on the
+        // screen, so horizontal and vertical flips swap the other axis.
+        let diagonal = (flips & FLIP_DIAGONAL) != 0;
+        let (flip_tex_x, flip_tex_y) = if diagonal {
+            ((flips & FLIP_VERTICAL) != 0, (flips & FLIP_HORIZONTAL) != 0)
+        } else {
+            ((flips & FLIP_HORIZONTAL) != 0, (flips & FLIP_VERTICAL) != 0)
+        };
+
+        if flip_tex_x {
+            std::mem::swap(&mut src_left, &mut src_right);
+        }
+
+        if flip_tex_y {
+            std::mem::swap(&mut src_top, &mut src_bottom);
+        }
+
+        let ul = position;
+        let ur = (position.0 + TILE_SIZE, position.1);
+        let ll = (position.0, position.1 + TILE_SIZE);
+        let lr = (position.0 + TILE_SIZE, position.1 + TILE_SIZE);
+
+        // The diagonal flip is done by swapping the upper right and lower
+        // left corners, which transposes the texture.
+        let (ur, ll) = if diagonal { (ll, ur) } else { (ur, ll) };
+        context.draw_quad(ul, ur, ll, lr, src_left, src_top, src_right, src_bottom);
+    }
+
+    fn draw_layer(
+        &self,
+        context: &mut gfx::RenderContext,
+        layer: &TileLayer,
+        visible_rect: &util::Rect<i32>,
+    ) {
+        let layer_view = Self::get_layer_view(layer, visible_rect);
+        context.set_offset(layer_view.left, layer_view.top);
+
+        let left_tile = std::cmp::max(layer_view.left / TILE_SIZE, 0);
+        let right_tile =
+            std::cmp::min((layer_view.right() + TILE_SIZE - 1) / TILE_SIZE, self.width);
+        let top_tile = std::cmp::max(layer_view.top / TILE_SIZE, 0);
         let bottom_tile = std::cmp::min(
-            (visible_rect.bottom() + TILE_SIZE - 1) / TILE_SIZE,
+            (layer_view.bottom() + TILE_SIZE - 1) / TILE_SIZE,
             self.height,
         );
 
         for y in top_tile..bottom_tile {
             for x in left_tile..right_tile {
-                let tile = self.tiles[(y * self.width + x) as usize];
-                if tile != 0 {
-                    context.draw_image(
-                        (TILE_SIZE * x, TILE_SIZE * y),
-                        &self.atlas_coords[tile as usize - 1],
-                        0.0,
-                        false,
+                let index = (y * self.width + x) as usize;
+                let tile = layer.tiles[index];
+                if tile == 0 {
+                    continue;
+                }
+
+                let sprite = &self.atlas_coords[self.current_images[tile as usize - 1]];
+                let flips = layer.flips[index];
+                if flips == 0 {
+                    context.draw_image((TILE_SIZE * x, TILE_SIZE * y), sprite, 0.0, false);
+                } else {
+                    Self::draw_flipped_tile(
+                        context,
+                        (
+                            TILE_SIZE * x - layer_view.left,
+                            TILE_SIZE * y - layer_view.top,
+                        ),
+                        sprite,
+                        flips,
                     );
                 }
             }
         }
+
+        context.set_offset(visible_rect.left, visible_rect.top);
+    }
+}
+
+// Build a map from a picture, for tests. Each string is a row, with '#'
+// for solid tiles, 'H' for ladders, '~' for water, '-' for one way platforms,
+// '/' and '\' for 45 degree slopes, '%' for breakable bricks, and anything
+// else empty.
+#[cfg(test)]
+pub fn create_map_from_text(rows: &[&str]) -> TileMap {
+    let tiles: Vec<u16> = rows
+        .iter()
+        .flat_map(|row| {
+            row.chars().map(|c| match c {
+                '#' => 1,
+                'H' => 2,
+                '~' => 3,
+                '-' => 4,
+                '/' => 5,
+                '\\' => 6,
+                '%' => 7,
+                _ => 0,
+            })
+        })
+        .collect();
+
+    TileMap {
+        width: rows[0].len() as i32,
+        height: rows.len() as i32,
+        layers: vec![TileLayer {
+            name: String::from("collision"),
+            flips: vec![0; tiles.len()],
+            tiles,
+            parallax_x: 1.0,
+            parallax_y: 1.0,
+            is_foreground: false,
+        }],
+        tile_flags: vec![
+            FLAG_SOLID,
+            FLAG_LADDER,
+            FLAG_WATER,
+            FLAG_ONE_WAY,
+            1 << SLOPE_SHIFT,
+            2 << SLOPE_SHIFT,
+            FLAG_SOLID | FLAG_BREAKABLE,
+        ],
+        current_images: (0..7).collect(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x4 map with a single solid tile at (2, 1).
+    fn create_test_map() -> TileMap {
+        create_map_from_text(&[
+            "....", //
+            "..#.", //
+            "....", //
+            "....", //
+        ])
+    }
+
+    #[test]
+    fn test_move_rect_unobstructed() {
+        let map = create_test_map();
+        let rect = util::Rect::<f32>::new(10.0, 10.0, 20.0, 20.0);
+        let (moved, collision) = map.move_rect(&rect, (30.0, 40.0));
+        assert_eq!(moved, util::Rect::<f32>::new(40.0, 50.0, 20.0, 20.0));
+        assert!(!collision.any());
+    }
+
+    #[test]
+    fn test_move_rect_blocked() {
+        let map = create_test_map();
+
+        // Moving right into the left side of the tile.
+        let rect = util::Rect::<f32>::new(70.0, 80.0, 20.0, 20.0);
+        let (moved, collision) = map.move_rect(&rect, (100.0, 0.0));
+        assert_eq!(moved.left, 108.0);
+        assert!(collision.right && !collision.left);
+
+        // Falling onto the top of the tile. The horizontal part of the motion
+        // still happens.
+        let rect = util::Rect::<f32>::new(140.0, 0.0, 20.0, 20.0);
+        let (moved, collision) = map.move_rect(&rect, (5.0, 50.0));
+        assert_eq!(moved, util::Rect::<f32>::new(145.0, 44.0, 20.0, 20.0));
+        assert_eq!(
+            collision,
+            TileCollision {
+                bottom: true,
+                ..Default::default()
+            }
+        );
+
+        // Moving up into the bottom and left into the right side.
+        let rect = util::Rect::<f32>::new(140.0, 150.0, 20.0, 20.0);
+        let (moved, collision) = map.move_rect(&rect, (0.0, -100.0));
+        assert_eq!(moved.top, 128.0);
+        assert!(collision.top);
+        let rect = util::Rect::<f32>::new(200.0, 70.0, 20.0, 20.0);
+        let (moved, collision) = map.move_rect(&rect, (-100.0, 0.0));
+        assert_eq!(moved.left, 192.0);
+        assert!(collision.left);
+    }
+
+    #[test]
+    fn test_move_rect_no_tunneling() {
+        // Moving much further than a tile in one step still stops at it.
+        let map = create_test_map();
+        let rect = util::Rect::<f32>::new(0.0, 100.0, 4.0, 4.0);
+        let (moved, collision) = map.move_rect(&rect, (2000.0, 0.0));
+        assert_eq!(moved.left, 124.0);
+        assert!(collision.right);
+    }
+
+    #[test]
+    fn test_move_rect_abutting() {
+        // Sliding along the top of a tile doesn't count as hitting it, and
+        // moving exactly up to it does.
+        let map = create_test_map();
+        let rect = util::Rect::<f32>::new(130.0, 44.0, 20.0, 20.0);
+        let (moved, collision) = map.move_rect(&rect, (50.0, 0.0));
+        assert_eq!(moved.left, 180.0);
+        assert!(!collision.any());
+
+        let (moved, collision) = map.move_rect(&rect, (0.0, 1.0));
+        assert_eq!(moved.top, 44.0);
+        assert!(collision.bottom);
+    }
+
+    #[test]
+    fn test_raycast() {
+        let map = create_test_map();
+
+        // Horizontal ray hits the left side of the tile.
+        let hit = map.raycast((10.0, 100.0), (250.0, 100.0)).unwrap();
+        assert_eq!(
+            hit,
+            RaycastHit {
+                tile_x: 2,
+                tile_y: 1,
+                point: (128.0, 100.0),
+                face: TileFace::Left,
+            }
+        );
+
+        // Diagonal ray coming up from below left hits the bottom.
+        let hit = map.raycast((100.0, 200.0), (180.0, 40.0)).unwrap();
+        assert_eq!((hit.tile_x, hit.tile_y), (2, 1));
+        assert_eq!(hit.face, TileFace::Bottom);
+        assert_eq!(hit.point, (136.0, 128.0));
+
+        // Falls short of the tile.
+        assert!(map.raycast((10.0, 100.0), (120.0, 100.0)).is_none());
+
+        // Starting inside the tile doesn't count.
+        assert!(map.raycast((140.0, 100.0), (250.0, 100.0)).is_none());
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let map = create_test_map();
+        assert!(!map.line_of_sight((100.0, 100.0), (200.0, 100.0)));
+        assert!(!map.line_of_sight((200.0, 100.0), (100.0, 100.0)));
+        assert!(map.line_of_sight((100.0, 10.0), (200.0, 10.0)));
+        assert!(map.line_of_sight((100.0, 100.0), (100.0, 100.0)));
+    }
+
+    #[test]
+    fn test_collision_layer() {
+        // Only the collision layer affects is_solid, even if other layers
+        // have solid tiles in them.
+        let mut map = create_test_map();
+        map.layers.insert(
+            0,
+            TileLayer {
+                name: String::from("background"),
+                tiles: vec![1; 16],
+                flips: vec![0; 16],
+                parallax_x: 0.5,
+                parallax_y: 0.5,
+                is_foreground: false,
+            },
+        );
+        map.collision_layer = 1;
+        assert!(!map.is_solid(10, 10));
+        assert!(map.is_solid(130, 70));
+    }
+
+    #[test]
+    fn test_parallax() {
+        let layer = TileLayer {
+            name: String::from("background"),
+            tiles: Vec::new(),
+            flips: Vec::new(),
+            parallax_x: 0.5,
+            parallax_y: 0.25,
+            is_foreground: false,
+        };
+        assert_eq!(
+            TileMap::get_layer_view(&layer, &util::Rect::<i32>::new(200, 400, 800, 450)),
+            util::Rect::<i32>::new(100, 100, 800, 450)
+        );
+    }
+
+    #[test]
+    fn test_water() {
+        let map = create_map_from_text(&[
+            "#~~#", //
+            "####", //
+        ]);
+        assert!(map.is_water_tile(1, 0));
+        assert!(map.is_water(2 * TILE_SIZE + 10, 20));
+        assert!(!map.is_water(10, 20));
+        assert!(!map.is_solid_tile(1, 0));
+        assert!(!map.is_water_tile(1, 1));
+        assert!(!map.is_water_tile(1, -1));
+    }
+
+    #[test]
+    fn test_one_way_platform() {
+        let map = create_map_from_text(&[
+            "....", //
+            "..-.", //
+            "....", //
+        ]);
+
+        // Lands on it from above
+        let rect = util::Rect::<f32>::new(130.0, 10.0, 20.0, 40.0);
+        let (moved, collision) = map.move_rect(&rect, (0.0, 30.0));
+        assert_eq!(moved.top, 24.0);
+        assert!(collision.bottom);
+
+        // Falls through when asked to
+        let (moved, collision) = map.move_rect_through_platforms(&rect, (0.0, 30.0));
+        assert_eq!(moved.top, 40.0);
+        assert!(!collision.any());
+
+        // Jumps through from below
+        let rect = util::Rect::<f32>::new(130.0, 140.0, 20.0, 40.0);
+        let (moved, collision) = map.move_rect(&rect, (0.0, -100.0));
+        assert_eq!(moved.top, 40.0);
+        assert!(!collision.any());
+
+        // Doesn't land on it when starting partway through it
+        let rect = util::Rect::<f32>::new(130.0, 40.0, 20.0, 40.0);
+        let (moved, collision) = map.move_rect(&rect, (0.0, 10.0));
+        assert_eq!(moved.top, 50.0);
+        assert!(!collision.any());
+
+        // Walks through it sideways
+        let (moved, collision) = map.move_rect(&rect, (100.0, 0.0));
+        assert_eq!(moved.left, 230.0);
+        assert!(!collision.any());
+    }
+
+    #[test]
+    fn test_slope_height() {
+        assert_eq!(Slope::Rise45.height_at(0.0), 0.0);
+        assert_eq!(Slope::Rise45.height_at(16.0), 16.0);
+        assert_eq!(Slope::Fall45.height_at(16.0), 48.0);
+        assert_eq!(Slope::Rise22Low.height_at(32.0), 16.0);
+        assert_eq!(Slope::Rise22High.height_at(32.0), 48.0);
+        assert_eq!(Slope::Fall22High.height_at(64.0), 32.0);
+        assert_eq!(Slope::Fall22Low.height_at(0.0), 32.0);
+        assert_eq!(Slope::Fall22Low.height_at(100.0), 0.0);
+    }
+
+    #[test]
+    fn test_slope_surface() {
+        let map = create_map_from_text(&[
+            "....",  //
+            "./\\.", //
+            "####",  //
+        ]);
+        assert_eq!(map.get_slope_tile(1, 1), Some(Slope::Rise45));
+        assert_eq!(map.get_slope_tile(2, 1), Some(Slope::Fall45));
+        assert_eq!(map.get_slope_tile(0, 1), None);
+        assert!(!map.is_solid_tile(1, 1));
+
+        assert_eq!(map.find_floor(80.0, 0.0, 150.0), Some(112.0));
+        assert_eq!(map.find_floor(150.0, 0.0, 150.0), Some(86.0));
+        assert_eq!(map.find_floor(80.0, 0.0, 100.0), None);
+        assert_eq!(map.find_floor(20.0, 0.0, 150.0), Some(128.0));
+        assert_eq!(map.find_floor(20.0, 0.0, 100.0), None);
+
+        assert!(map.is_inside_slope(80.0, 120.0));
+        assert!(!map.is_inside_slope(80.0, 100.0));
+        assert!(!map.is_inside_slope(20.0, 120.0));
+    }
+
+    #[test]
+    fn test_set_tile() {
+        let mut map = create_map_from_text(&[
+            "....", //
+            ".%%.", //
+            "####", //
+        ]);
+        assert!(map.is_breakable_tile(1, 1));
+        assert!(map.is_solid_tile(1, 1));
+        assert!(!map.is_breakable_tile(1, 2));
+        assert_eq!(map.take_dirty_region(), None);
+
+        assert!(map.set_tile(1, 1, 0));
+        assert!(!map.is_solid_tile(1, 1));
+        assert_eq!(map.get_tile(1, 1), 0);
+        assert!(map.set_tile(3, 0, 1));
+        assert!(map.is_solid_tile(3, 0));
+        assert!(!map.set_tile(4, 0, 1));
+        assert!(!map.set_tile(0, -1, 1));
+        let num_tiles = map.tile_flags.len() as u16;
+        assert!(!map.set_tile(0, 0, num_tiles + 1));
+        assert_eq!(map.get_tile(0, 0), 0);
+        assert_eq!(
+            map.take_dirty_region(),
+            Some(util::Rect::<i32>::new(1, 0, 3, 2))
+        );
+        assert_eq!(map.take_dirty_region(), None);
+
+        let edits = map.get_modifications();
+        assert_eq!(
+            edits,
+            vec![
+                TileEdit {
+                    x: 1,
+                    y: 1,
+                    tile: 0
+                },
+                TileEdit {
+                    x: 3,
+                    y: 0,
+                    tile: 1
+                },
+            ]
+        );
+
+        // Apply to a freshly loaded copy of the map
+        let mut map = create_map_from_text(&[
+            "....", //
+            ".%%.", //
+            "####", //
+        ]);
+        map.restore_modifications(&edits);
+        assert!(!map.is_solid_tile(1, 1));
+        assert!(map.is_solid_tile(2, 1));
+        assert!(map.is_solid_tile(3, 0));
+    }
+
+    #[test]
+    fn test_object_properties() {
+        let mut object = MapObject::new("Bat", 10, 20);
+        object
+            .properties
+            .insert(String::from("range"), PropertyValue::Int(300));
+        object
+            .properties
+            .insert(String::from("speed"), PropertyValue::Float(1.5));
+        object
+            .properties
+            .insert(String::from("angry"), PropertyValue::Bool(true));
+        object.properties.insert(
+            String::from("color"),
+            PropertyValue::String(String::from("red")),
+        );
+
+        assert_eq!(object.get_int("range"), Some(300));
+        assert_eq!(object.get_float("range"), Some(300.0));
+        assert_eq!(object.get_float("speed"), Some(1.5));
+        assert_eq!(object.get_int("speed"), None);
+        assert_eq!(object.get_bool("angry"), Some(true));
+        assert_eq!(object.get_string("color"), Some("red"));
+        assert_eq!(object.get_string("missing"), None);
+        assert_eq!(object.rect, util::Rect::<i32>::new(10, 20, 0, 0));
+    }
+
+    #[test]
+    fn test_find_spawn_point() {
+        let mut map = create_test_map();
+        map.player_start_x = 64;
+        map.player_start_y = 128;
+        let mut spawn = MapObject::new(SPAWN_TYPE, 320, 256);
+        spawn.name = String::from("door");
+        map.spawn_points.push(spawn);
+
+        assert_eq!(map.find_spawn_point("door"), Some((320, 256)));
+        assert_eq!(map.find_spawn_point(""), Some((64, 128)));
+        assert_eq!(map.find_spawn_point("window"), None);
+    }
+
+    #[test]
+    fn test_large_tile_ids() {
+        let mut map = create_test_map();
+        map.tile_flags.resize(299, 0);
+        map.tile_flags.push(FLAG_LADDER);
+        map.layers[0].tiles[0] = 300;
+        assert!(map.is_ladder_tile(0, 0));
+        assert!(!map.is_solid_tile(0, 0));
+    }
+
+    #[test]
+    fn test_draw_flipped() {
+        // The texture has a different color in each quadrant.
+        const RED: image::Rgba<u8> = image::Rgba([255, 0, 0, 255]);
+        const GREEN: image::Rgba<u8> = image::Rgba([0, 255, 0, 255]);
+        const BLUE: image::Rgba<u8> = image::Rgba([0, 0, 255, 255]);
+        const BLACK: image::Rgba<u8> = image::Rgba([0, 0, 0, 255]);
+        let atlas = image::RgbaImage::from_fn(8, 8, |x, y| match (x < 4, y < 4) {
+            (true, true) => RED,
+            (false, true) => GREEN,
+            (true, false) => BLUE,
+            (false, false) => BLACK,
+        });
+        let atlas_path =
+            std::env::temp_dir().join(format!("flip-test-atlas-{}.png", std::process::id()));
+        atlas.save(&atlas_path).unwrap();
+        let mut context = gfx::RenderContext::new_software(&atlas_path);
+        std::fs::remove_file(&atlas_path).unwrap();
+
+        // Draw one tile with each combination of flips in a row.
+        let mut map = create_map_from_text(&["........"]);
+        map.atlas_coords = vec![(0.0, 0.0, 1.0, 1.0, TILE_SIZE, TILE_SIZE, 0, 0)];
+        map.layers[0].tiles = vec![1; 8];
+        map.layers[0].flips = (0..8).collect();
+        map.draw_background(
+            &mut context,
+            &util::Rect::<i32>::new(0, 0, gfx::WINDOW_WIDTH, gfx::WINDOW_HEIGHT),
+        );
+        context.render();
+
+        // Upper left, upper right, lower left, lower right
+        let expected = [
+            [RED, GREEN, BLUE, BLACK], // none
+            [GREEN, RED, BLACK, BLUE], // horizontal
+            [BLUE, BLACK, RED, GREEN], // vertical
+            [BLACK, BLUE, GREEN, RED], // both (rotate 180)
+            [RED, BLUE, GREEN, BLACK], // diagonal
+            [BLUE, RED, BLACK, GREEN], // diagonal + horizontal (rotate 90 CW)
+            [GREEN, BLACK, RED, BLUE], // diagonal + vertical (rotate 90 CCW)
+            [BLACK, GREEN, BLUE, RED], // all
+        ];
+        let framebuffer = context.framebuffer().unwrap();
+        for (index, colors) in expected.iter().enumerate() {
+            let left = index as u32 * TILE_SIZE as u32;
+            let actual = [
+                *framebuffer.get_pixel(left + 8, 8),
+                *framebuffer.get_pixel(left + 56, 8),
+                *framebuffer.get_pixel(left + 8, 56),
+                *framebuffer.get_pixel(left + 56, 56),
+            ];
+            assert_eq!(&actual, colors, "flips {}", index);
+        }
+    }
+
+    #[test]
+    fn test_tile_animation() {
+        let mut map = create_test_map();
+        map.current_images = vec![0, 1, 2];
+        map.animations.push(TileAnimation {
+            tile_index: 1,
+            frames: vec![(1, 0.5), (2, 0.25)],
+            total_duration: 0.75,
+            time: 0.0,
+        });
+
+        map.update(0.25);
+        assert_eq!(map.current_images, vec![0, 1, 2]);
+        map.update(0.375);
+        assert_eq!(map.current_images, vec![0, 2, 2]);
+
+        // Wraps around
+        map.update(0.25);
+        assert_eq!(map.current_images, vec![0, 1, 2]);
+        assert!(map.animations[0].time < 0.75);
+
+        // Small steps still count after running a long time (at this
+        // magnitude, an f32 total time would have a precision of 2
+        // seconds).
+        map.update(16777216.0);
+        assert_eq!(map.current_images, vec![0, 1, 2]);
+        map.update(0.25);
+        assert_eq!(map.current_images, vec![0, 2, 2]);
     }
 }
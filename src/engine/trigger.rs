@@ -0,0 +1,171 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Triggers are rectangles placed in the map with the object type "Trigger".
+// Each tick, the engine checks whether the player is inside each one, and
+// calls the function registered for the trigger's name when the player enters,
+// stays inside, or leaves (see GameEngine::register_trigger). This allows
+// checkpoints, music changes, ambushes, etc. without a custom entity for each.
+// A trigger with the bool property "once" stops firing after the player
+// leaves it the first time.
+
+use crate::tilemap::MapObject;
+use crate::util;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerEvent {
+    Enter,
+    Stay,
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TriggerState {
+    Outside,
+    Inside,
+    Finished,
+}
+
+#[derive(Default)]
+pub struct TriggerTracker {
+    // One entry per trigger in the map, in the same order.
+    states: Vec<TriggerState>,
+}
+
+impl TriggerTracker {
+    // Call this when a new map is loaded.
+    pub fn reset(&mut self) {
+        self.states.clear();
+    }
+
+    // Returns the index of each trigger that fired this tick along with the
+    // event. player_rect is None if there is no player, which is treated as
+    // being outside all triggers.
+    pub fn update(
+        &mut self,
+        triggers: &[MapObject],
+        player_rect: Option<&util::Rect<i32>>,
+    ) -> Vec<(usize, TriggerEvent)> {
+        self.states.resize(triggers.len(), TriggerState::Outside);
+
+        let mut events = Vec::new();
+        for (index, trigger) in triggers.iter().enumerate() {
+            let inside = player_rect.is_some_and(|rect| rect.overlaps(&trigger.rect));
+            match (self.states[index], inside) {
+                (TriggerState::Outside, true) => {
+                    self.states[index] = TriggerState::Inside;
+                    events.push((index, TriggerEvent::Enter));
+                }
+                (TriggerState::Inside, true) => {
+                    events.push((index, TriggerEvent::Stay));
+                }
+                (TriggerState::Inside, false) => {
+                    self.states[index] = if trigger.get_bool("once").unwrap_or(false) {
+                        TriggerState::Finished
+                    } else {
+                        TriggerState::Outside
+                    };
+
+                    events.push((index, TriggerEvent::Exit));
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::PropertyValue;
+
+    fn create_trigger(x: i32, y: i32) -> MapObject {
+        let mut trigger = MapObject::new("Trigger", x, y);
+        trigger.rect.width = 64;
+        trigger.rect.height = 64;
+        trigger
+    }
+
+    #[test]
+    fn test_enter_stay_exit() {
+        let triggers = vec![create_trigger(100, 100)];
+        let mut tracker = TriggerTracker::default();
+        let outside = util::Rect::<i32>::new(0, 100, 32, 32);
+        let inside = util::Rect::<i32>::new(120, 120, 32, 32);
+
+        assert!(tracker.update(&triggers, Some(&outside)).is_empty());
+        assert_eq!(
+            tracker.update(&triggers, Some(&inside)),
+            vec![(0, TriggerEvent::Enter)]
+        );
+        assert_eq!(
+            tracker.update(&triggers, Some(&inside)),
+            vec![(0, TriggerEvent::Stay)]
+        );
+        assert_eq!(
+            tracker.update(&triggers, Some(&outside)),
+            vec![(0, TriggerEvent::Exit)]
+        );
+        assert!(tracker.update(&triggers, Some(&outside)).is_empty());
+
+        // Can fire again.
+        assert_eq!(
+            tracker.update(&triggers, Some(&inside)),
+            vec![(0, TriggerEvent::Enter)]
+        );
+
+        // No player is the same as leaving.
+        assert_eq!(
+            tracker.update(&triggers, None),
+            vec![(0, TriggerEvent::Exit)]
+        );
+    }
+
+    #[test]
+    fn test_multiple_triggers() {
+        let triggers = vec![create_trigger(0, 0), create_trigger(200, 0)];
+        let mut tracker = TriggerTracker::default();
+        let rect = util::Rect::<i32>::new(210, 10, 32, 32);
+        assert_eq!(
+            tracker.update(&triggers, Some(&rect)),
+            vec![(1, TriggerEvent::Enter)]
+        );
+    }
+
+    #[test]
+    fn test_once() {
+        let mut trigger = create_trigger(100, 100);
+        trigger
+            .properties
+            .insert(String::from("once"), PropertyValue::Bool(true));
+        let triggers = vec![trigger];
+        let mut tracker = TriggerTracker::default();
+        let outside = util::Rect::<i32>::new(0, 100, 32, 32);
+        let inside = util::Rect::<i32>::new(120, 120, 32, 32);
+
+        assert_eq!(
+            tracker.update(&triggers, Some(&inside)),
+            vec![(0, TriggerEvent::Enter)]
+        );
+        assert_eq!(
+            tracker.update(&triggers, Some(&outside)),
+            vec![(0, TriggerEvent::Exit)]
+        );
+        assert!(tracker.update(&triggers, Some(&inside)).is_empty());
+        assert!(tracker.update(&triggers, Some(&outside)).is_empty());
+    }
+}
@@ -17,7 +17,13 @@
 use std::io::Read;
 use std::path::PathBuf;
 
-#[derive(Clone, Copy, Default, Debug)]
+// All randomness in the simulation should come from the engine's instance of
+// this, so runs can be reproduced from a seed (see replay.rs). This is a
+// specific algorithm rather than StdRng, which may change between versions
+// of rand. If it is changed, REPLAY_VERSION in replay.rs must be too.
+pub type GameRng = rand_chacha::ChaCha8Rng;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct Rect<T> {
     pub left: T,
     pub top: T,
@@ -51,6 +57,10 @@ impl<T: std::ops::Add<Output = T> + std::cmp::PartialOrd<T> + Clone + Copy> Rect
     }
 }
 
+pub fn lerp(start: f32, end: f32, t: f32) -> f32 {
+    start + (end - start) * t
+}
+
 pub struct StructuredFileReader {
     reader: std::io::BufReader<std::fs::File>,
 }
@@ -63,6 +73,18 @@ impl StructuredFileReader {
         StructuredFileReader { reader }
     }
 
+    pub fn read_u8(&mut self) -> u8 {
+        let mut buf = [0u8; 1];
+        self.reader.read_exact(&mut buf).unwrap();
+        buf[0]
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        let mut buf = [0u8; 2];
+        self.reader.read_exact(&mut buf).unwrap();
+        u16::from_le_bytes(buf)
+    }
+
     pub fn read_i32(&mut self) -> i32 {
         let mut buf = [0u8; 4];
         self.reader.read_exact(&mut buf).unwrap();
@@ -138,6 +160,15 @@ mod tests {
     }
 
     #[test]
+    fn test_lerp() {
+        assert_eq!(lerp(10.0, 20.0, 0.0), 10.0);
+        assert_eq!(lerp(10.0, 20.0, 0.5), 15.0);
+        assert_eq!(lerp(10.0, 20.0, 1.0), 20.0);
+        assert_eq!(lerp(20.0, 10.0, 0.25), 17.5);
+    }
+
+    #[test]
+    #[allow(clippy::clone_on_copy)]
     fn test_copy_clone() {
         let r1 = Rect::<i32>::new(1, 2, 3, 4);
         let r2 = r1;
@@ -18,9 +18,10 @@ use crate::assets;
 use engine::audio;
 use engine::entity;
 use engine::gfx;
+use engine::input;
+use engine::pathfind;
 use engine::tilemap;
 use engine::util;
-use rand::rngs::ThreadRng;
 use rand::Rng;
 use std::any::Any;
 
@@ -33,10 +34,39 @@ pub const COLL_OBJ: u32 = 4;
 pub const COLL_ENEMY: u32 = 8;
 
 pub const ENTITY_LIST: &[(&str, engine::EntityCreateFn)] = &[
-    ("Balloon", |x, y| Box::new(Balloon::new(x as f32, y as f32))),
-    ("Bat", |x, y| Box::new(Bat::new(x as f32, y as f32))),
+    ("Balloon", |object| {
+        Box::new(Balloon::new(
+            object.rect.left as f32,
+            object.rect.top as f32,
+        ))
+    }),
+    ("Bat", |object| Box::new(Bat::from_object(object))),
 ];
 
+// Functions for trigger zones in the map, by trigger name.
+pub const TRIGGER_LIST: &[(&str, engine::TriggerFn)] = &[("BatAmbush", bat_ambush)];
+
+const DEFAULT_AMBUSH_BATS: i32 = 3;
+
+// Release bats along the top edge of the trigger when the player walks in.
+// The number is set by the int property "count".
+fn bat_ambush(
+    eng: &mut engine::GameEngine,
+    trigger: &tilemap::MapObject,
+    event: engine::trigger::TriggerEvent,
+) {
+    if event != engine::trigger::TriggerEvent::Enter {
+        return;
+    }
+
+    let count = trigger.get_int("count").unwrap_or(DEFAULT_AMBUSH_BATS);
+    let spacing = trigger.rect.width as f32 / count as f32;
+    for i in 0..count {
+        let xpos = trigger.rect.left as f32 + spacing * (i as f32 + 0.5);
+        eng.spawn(Box::new(Bat::new(xpos, trigger.rect.top as f32)));
+    }
+}
+
 pub struct Player {
     bow_angle: f32,
 
@@ -44,6 +74,8 @@ pub struct Player {
     // level (since the bow pivots around this point).
     xpos: f32,
     ypos: f32,
+    prev_xpos: f32,
+    prev_ypos: f32,
     bow_drawn: bool,
     bow_draw_time: f32,
     facing_left: bool,
@@ -53,22 +85,55 @@ pub struct Player {
     jump_counter: u32,
     frame_time: f32,
     yvec: f32,
-    last_jump_button: bool,
     killed: bool,
     climbing: bool,
-    ground_offset: i32, // Distance from origin to ground
+    swimming: bool,
+    breath: f32,            // Seconds left before drowning
+    drop_through_time: f32, // Ignore one way platforms while this is > 0
+    ground_offset: i32,     // Distance from origin to ground
 }
 
 const RUN_FRAME_DURATION: f32 = 0.1;
 const MAX_JUMP_COUNTER: u32 = 5;
+const RUN_SPEED: f32 = 150.0;
+const CLIMB_SPEED: f32 = 128.0;
+
+// Size of the box used to collide with tiles, relative to the origin.
+const PLAYER_HALF_WIDTH: f32 = 14.0;
+const PLAYER_HEAD_OFFSET: f32 = 20.0;
+
+const STOMP_BOUNCE_SPEED: f32 = 400.0;
+
+// Holding the bow this long fires the fastest arrow, which is also heavy
+// enough to break bricks.
+const FULL_DRAW_TIME: f32 = 0.4;
+
+// Movement in water. The player sinks slowly unless swimming up, and drag
+// limits how fast they can move either way.
+const SWIM_GRAVITY: f32 = 300.0;
+const SWIM_STROKE_ACCEL: f32 = 900.0;
+const SWIM_DRAG: f32 = 3.0;
+const SWIM_SPEED: f32 = 100.0;
+const SWIM_JUMP_SPEED: f32 = 450.0;
+const MAX_BREATH: f32 = 8.0;
+
+// How far the player's feet can move up or down to follow the surface of a
+// slope while walking. This is also how much of the bottom of the player is
+// ignored when checking for walls while on the ground, so they can walk up a
+// slope into the tile at the top.
+const SLOPE_SNAP_DISTANCE: f32 = 16.0;
+const DROP_THROUGH_TIME: f32 = 0.1;
 
 impl Player {
     pub fn new(xpos: f32, ypos: f32) -> Player {
         let ground_offset = assets::SPR_PLAYER_BODY_IDLE.5 - assets::SPR_PLAYER_BODY_IDLE.7;
+        let ypos = ypos + 64.0 - ground_offset as f32;
         Player {
             bow_angle: -std::f32::consts::PI / 4.0,
             xpos,
-            ypos: ypos + 64.0 - ground_offset as f32,
+            ypos,
+            prev_xpos: xpos,
+            prev_ypos: ypos,
             bow_drawn: false,
             bow_draw_time: 0.0,
             facing_left: false,
@@ -78,60 +143,151 @@ impl Player {
             jump_counter: MAX_JUMP_COUNTER,
             frame_time: 0.0,
             yvec: 0.0,
-            last_jump_button: false,
             killed: false,
             climbing: false,
+            swimming: false,
+            breath: MAX_BREATH,
+            drop_through_time: 0.0,
             ground_offset,
         }
     }
-}
 
-impl entity::Entity for Player {
-    fn update(
-        &mut self,
-        d_t: f32,
-        new_entities: &mut Vec<Box<dyn entity::Entity>>,
-        buttons: u32,
+    fn get_collision_rect(&self) -> util::Rect<f32> {
+        util::Rect::<f32>::new(
+            self.xpos - PLAYER_HALF_WIDTH,
+            self.ypos - PLAYER_HEAD_OFFSET,
+            PLAYER_HALF_WIDTH * 2.0,
+            PLAYER_HEAD_OFFSET + self.ground_offset as f32,
+        )
+    }
+
+    fn set_collision_rect(&mut self, rect: &util::Rect<f32>) {
+        self.xpos = rect.left + PLAYER_HALF_WIDTH;
+        self.ypos = rect.top + PLAYER_HEAD_OFFSET;
+    }
+
+    fn move_rect(
+        &self,
         tile_map: &tilemap::TileMap,
-        _player_rect: &util::Rect<i32>,
-    ) {
-        if self.killed {
-            if !tile_map.is_solid(self.xpos as i32 - 30, self.ypos as i32 + 45)
-                && !tile_map.is_solid(self.xpos as i32 + 30, self.ypos as i32 + 45)
+        rect: &util::Rect<f32>,
+        delta: (f32, f32),
+    ) -> (util::Rect<f32>, tilemap::TileCollision) {
+        if self.drop_through_time > 0.0 {
+            tile_map.move_rect_through_platforms(rect, delta)
+        } else {
+            tile_map.move_rect(rect, delta)
+        }
+    }
+
+    // Returns the y coordinate of the floor (see TileMap::find_floor) at most
+    // `above` pixels above or `below` pixels below the player's feet.
+    fn find_floor(&self, tile_map: &tilemap::TileMap, above: f32, below: f32) -> Option<f32> {
+        let feet = self.ypos + self.ground_offset as f32;
+        tile_map.find_floor(self.xpos, feet - above, feet + below)
+    }
+
+    // Walking, jumping, and falling when not in water.
+    fn update_jump(&mut self, d_t: f32, input: &input::InputState, tile_map: &tilemap::TileMap) {
+        // Standing on something if moving down at all would hit it.
+        let rect = self.get_collision_rect();
+        let (_, below) = self.move_rect(tile_map, &rect, (0.0, 1.0));
+        self.on_ground = below.bottom || self.find_floor(tile_map, 0.0, 1.0).is_some();
+
+        if self.on_ground {
+            let (_, solid_below) = tile_map.move_rect_through_platforms(&rect, (0.0, 1.0));
+            let on_platform = below.bottom && !solid_below.bottom;
+            if on_platform
+                && input.held(entity::CONTROL_DOWN)
+                && input.pressed(entity::CONTROL_JUMP)
             {
-                self.ypos += 4.0;
+                // Drop through the platform
+                self.drop_through_time = DROP_THROUGH_TIME;
+                self.on_ground = false;
+                self.yvec = 0.0;
+                self.jump_counter = MAX_JUMP_COUNTER;
+            } else if input.pressed(entity::CONTROL_JUMP) {
+                self.yvec = -100.0;
+                self.jump_counter = 0;
+            } else {
+                self.yvec = 0.0;
+            }
+        } else if self.jump_counter < MAX_JUMP_COUNTER {
+            // Size of jump is proportional to how long the button is held
+            if input.held(entity::CONTROL_JUMP) {
+                self.yvec -= 5000.0 * d_t;
+                self.jump_counter += 1;
+            } else {
+                // If you let off button, stops increasing jump height
+                self.jump_counter = MAX_JUMP_COUNTER;
             }
+        } else {
+            // In air
+            self.is_running = false;
+            if self.yvec < 500.0 {
+                self.yvec += GRAVITY * d_t;
+            }
+        }
+    }
+
+    fn swim(&mut self, d_t: f32, input: &input::InputState, head_in_water: bool) {
+        self.on_ground = false;
+        self.jump_counter = MAX_JUMP_COUNTER;
+        if !head_in_water && input.pressed(entity::CONTROL_JUMP) {
+            // Jump out at the surface
+            self.yvec = -SWIM_JUMP_SPEED;
+        } else if input.held(entity::CONTROL_UP | entity::CONTROL_JUMP) {
+            self.yvec -= SWIM_STROKE_ACCEL * d_t;
+        } else {
+            self.yvec += SWIM_GRAVITY * d_t;
+        }
 
+        self.yvec -= self.yvec * (SWIM_DRAG * d_t).min(1.0);
+    }
+}
+
+impl entity::Entity for Player {
+    fn update(&mut self, ctx: &mut entity::UpdateContext) {
+        self.prev_xpos = self.xpos;
+        self.prev_ypos = self.ypos;
+
+        if self.killed {
+            // Fall until the body hits the ground.
+            let body_rect = util::Rect::<f32>::new(self.xpos - 30.0, self.ypos + 40.0, 60.0, 5.0);
+            let (body_rect, _) = ctx.tile_map.move_rect(&body_rect, (0.0, 4.0));
+            self.ypos = body_rect.top - 40.0;
             return;
         }
 
-        let on_ladder = tile_map.is_ladder(self.xpos as i32, self.ypos as i32)
-            || tile_map.is_ladder(self.xpos as i32, self.ypos as i32 + self.ground_offset);
+        let on_ladder = ctx.tile_map.is_ladder(self.xpos as i32, self.ypos as i32)
+            || ctx
+                .tile_map
+                .is_ladder(self.xpos as i32, self.ypos as i32 + self.ground_offset);
         if self.climbing {
             if !on_ladder {
                 self.climbing = false;
             }
 
-            if buttons & entity::CONTROL_UP != 0
-                && !tile_map.is_solid(self.xpos as i32, self.ypos as i32)
-            {
-                self.ypos -= 128.0 * d_t;
-            } else if buttons & entity::CONTROL_DOWN != 0
-                && !tile_map.is_solid(self.xpos as i32, self.ypos as i32 + self.ground_offset)
-            {
-                self.ypos += 128.0 * d_t;
+            let mut climb_vec = (0.0, 0.0);
+            if ctx.input.held(entity::CONTROL_UP) {
+                climb_vec.1 = -CLIMB_SPEED;
+            } else if ctx.input.held(entity::CONTROL_DOWN) {
+                climb_vec.1 = CLIMB_SPEED;
             }
 
-            if buttons & entity::CONTROL_LEFT != 0 {
-                self.xpos -= 128.0 * d_t;
-            } else if buttons & entity::CONTROL_RIGHT != 0 {
-                self.xpos += 128.0 * d_t;
+            if ctx.input.held(entity::CONTROL_LEFT) {
+                climb_vec.0 = -CLIMB_SPEED;
+            } else if ctx.input.held(entity::CONTROL_RIGHT) {
+                climb_vec.0 = CLIMB_SPEED;
             }
 
+            // Ladders can go through platforms
+            let (rect, _) = ctx.tile_map.move_rect_through_platforms(
+                &self.get_collision_rect(),
+                (climb_vec.0 * ctx.d_t, climb_vec.1 * ctx.d_t),
+            );
+            self.set_collision_rect(&rect);
             return;
-        } else if on_ladder
-            && (buttons & entity::CONTROL_UP != 0 || buttons & entity::CONTROL_DOWN != 0)
-        {
+        } else if on_ladder && ctx.input.held(entity::CONTROL_UP | entity::CONTROL_DOWN) {
             self.climbing = true;
             self.bow_drawn = false;
 
@@ -141,24 +297,27 @@ impl entity::Entity for Player {
             return;
         }
 
-        if buttons & entity::CONTROL_FIRE == 0 {
-            // Button not pressed
+        if !ctx.input.held(entity::CONTROL_FIRE) {
+            // This doesn't check for the release edge, since the button may
+            // have been released while the game was paused.
             if self.bow_drawn {
-                // It was released
-                let velocity = self.bow_draw_time.clamp(0.2, 0.4) * 5000.0;
+                let velocity = self.bow_draw_time.clamp(0.2, FULL_DRAW_TIME) * 5000.0;
                 let arrow_angle = if self.facing_left {
                     std::f32::consts::PI - self.bow_angle
                 } else {
                     self.bow_angle
                 };
-                new_entities.push(Box::new(Arrow::new(
-                    // Give the arrow a little bit of a start so player doesn't
-                    // accidently collide with it.
-                    self.xpos + arrow_angle.cos() * 10.0,
-                    self.ypos + arrow_angle.sin() * 10.0,
-                    arrow_angle,
-                    velocity,
-                )));
+
+                // Give the arrow a little bit of a start so player doesn't
+                // accidently collide with it.
+                let x = self.xpos + arrow_angle.cos() * 10.0;
+                let y = self.ypos + arrow_angle.sin() * 10.0;
+                ctx.new_entities
+                    .push(Box::new(if self.bow_draw_time >= FULL_DRAW_TIME {
+                        Arrow::new_heavy(x, y, arrow_angle, velocity)
+                    } else {
+                        Arrow::new(x, y, arrow_angle, velocity)
+                    }));
 
                 audio::play_effect(assets::SFX_ARROW);
                 self.bow_drawn = false;
@@ -166,82 +325,94 @@ impl entity::Entity for Player {
         } else {
             // Button pressed
             if self.bow_drawn {
-                self.bow_draw_time += d_t;
+                self.bow_draw_time += ctx.d_t;
             } else {
                 self.bow_drawn = true;
                 self.bow_draw_time = 0.0;
             }
 
             // Player can adjust angle when bow is drawn.
-            if buttons & entity::CONTROL_UP != 0 && self.bow_angle > -std::f32::consts::PI / 2.0 {
-                self.bow_angle -= d_t * std::f32::consts::PI;
+            if ctx.input.held(entity::CONTROL_UP) && self.bow_angle > -std::f32::consts::PI / 2.0 {
+                self.bow_angle -= ctx.d_t * std::f32::consts::PI;
             }
 
-            if buttons & entity::CONTROL_DOWN != 0 && self.bow_angle < std::f32::consts::PI / 2.0 {
-                self.bow_angle += d_t * std::f32::consts::PI;
+            if ctx.input.held(entity::CONTROL_DOWN) && self.bow_angle < std::f32::consts::PI / 2.0 {
+                self.bow_angle += ctx.d_t * std::f32::consts::PI;
             }
         }
 
-        self.on_ground = tile_map
-            .is_solid(self.xpos as i32 - 12, self.ypos as i32 + self.ground_offset)
-            || tile_map.is_solid(self.xpos as i32 + 12, self.ypos as i32 + self.ground_offset);
-
-        if self.on_ground {
-            if buttons & entity::CONTROL_JUMP != 0 && !self.last_jump_button {
-                self.yvec = -100.0;
-                self.jump_counter = 0;
-            } else {
-                self.yvec = 0.0;
-
-                // Ensure it is on the ground.
-                self.ypos = (self.ypos / tilemap::TILE_SIZE_F).floor() * tilemap::TILE_SIZE_F
-                    + (tilemap::TILE_SIZE_F - self.ground_offset as f32);
-            }
-        } else if self.jump_counter < MAX_JUMP_COUNTER {
-            // Size of jump is proportional to how long the button is held
-            if buttons & entity::CONTROL_JUMP != 0 {
-                self.yvec -= 5000.0 * d_t;
-                self.jump_counter += 1;
-            } else {
-                // If you let off button, stops increasing jump height
-                self.jump_counter = MAX_JUMP_COUNTER;
+        // The player swims when their body is in water, and must hold their
+        // breath while their head is.
+        self.swimming = ctx.tile_map.is_water(self.xpos as i32, self.ypos as i32);
+        let head_in_water = ctx
+            .tile_map
+            .is_water(self.xpos as i32, (self.ypos - PLAYER_HEAD_OFFSET) as i32);
+        if head_in_water {
+            self.breath -= ctx.d_t;
+            if self.breath <= 0.0 {
+                self.killed = true;
+                audio::play_effect(assets::SFX_DEATH);
+                return;
             }
         } else {
-            // In air
-            self.is_running = false;
-            if self.yvec < 500.0 {
-                self.yvec += GRAVITY * d_t;
-            }
+            self.breath = MAX_BREATH;
         }
 
-        if self.yvec < 0.0 && tile_map.is_solid(self.xpos as i32, self.ypos as i32 - 20) {
-            // Bumped head while jumping
-            self.yvec = 0.0;
+        self.drop_through_time = (self.drop_through_time - ctx.d_t).max(0.0);
+        if self.swimming {
+            self.swim(ctx.d_t, ctx.input, head_in_water);
+        } else {
+            self.update_jump(ctx.d_t, ctx.input, ctx.tile_map);
         }
 
-        self.last_jump_button = buttons & entity::CONTROL_JUMP != 0;
-        self.ypos += self.yvec * d_t;
+        // This stops when bumping head while jumping or landing.
+        let (rect, collision) = self.move_rect(
+            ctx.tile_map,
+            &self.get_collision_rect(),
+            (0.0, self.yvec * ctx.d_t),
+        );
+        self.set_collision_rect(&rect);
+        if collision.top || collision.bottom {
+            self.yvec = 0.0;
+        }
 
         // Movement
-        if buttons & entity::CONTROL_LEFT != 0
-            && !tile_map.is_solid(
-                self.xpos as i32 - 16,
-                self.ypos as i32 + self.ground_offset - 3,
-            )
-            && !tile_map.is_solid(self.xpos as i32 - 16, self.ypos as i32 - 15)
-        {
-            self.xpos -= 150.0 * d_t;
-            self.facing_left = true;
-            self.is_running = self.on_ground;
-        } else if buttons & entity::CONTROL_RIGHT != 0
-            && !tile_map.is_solid(
-                self.xpos as i32 + 16,
-                self.ypos as i32 + self.ground_offset - 3,
-            )
-            && !tile_map.is_solid(self.xpos as i32 + 16, self.ypos as i32 - 15)
-        {
-            self.xpos += 150.0 * d_t;
-            self.facing_left = false;
+        let speed = if self.swimming { SWIM_SPEED } else { RUN_SPEED };
+        let xvec = if ctx.input.held(entity::CONTROL_LEFT) {
+            -speed
+        } else if ctx.input.held(entity::CONTROL_RIGHT) {
+            speed
+        } else {
+            0.0
+        };
+
+        let mut rect = self.get_collision_rect();
+        if self.on_ground {
+            rect.height -= SLOPE_SNAP_DISTANCE;
+        }
+
+        let (rect, collision) = self.move_rect(ctx.tile_map, &rect, (xvec * ctx.d_t, 0.0));
+        self.set_collision_rect(&rect);
+
+        // Stand on the surface of slopes, and step up onto the tile at the top
+        // of one. If the player was already on the ground, this also keeps
+        // them on it when walking down a slope.
+        if !self.swimming && self.yvec >= 0.0 {
+            let above = SLOPE_SNAP_DISTANCE + (xvec.abs() + self.yvec) * ctx.d_t;
+            let below = if self.on_ground {
+                SLOPE_SNAP_DISTANCE
+            } else {
+                0.0
+            };
+            if let Some(floor) = self.find_floor(ctx.tile_map, above, below) {
+                self.ypos = floor - self.ground_offset as f32;
+                self.yvec = 0.0;
+                self.on_ground = true;
+            }
+        }
+
+        if xvec != 0.0 && !collision.left && !collision.right {
+            self.facing_left = xvec < 0.0;
             self.is_running = self.on_ground;
         } else {
             self.is_running = false;
@@ -250,7 +421,7 @@ impl entity::Entity for Player {
         }
 
         if self.is_running {
-            self.frame_time += d_t;
+            self.frame_time += ctx.d_t;
             if self.frame_time > RUN_FRAME_DURATION {
                 self.frame_time -= RUN_FRAME_DURATION;
                 self.run_frame += 1;
@@ -261,14 +432,24 @@ impl entity::Entity for Player {
         }
     }
 
-    fn draw(&self, context: &mut gfx::RenderContext) {
+    // Keeps everything else (breath, bow, etc.) from the previous map.
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.xpos = x;
+        self.ypos = y + 64.0 - self.ground_offset as f32;
+        self.prev_xpos = self.xpos;
+        self.prev_ypos = self.ypos;
+        self.yvec = 0.0;
+        self.on_ground = false;
+        self.climbing = false;
+    }
+
+    fn draw(&self, context: &mut gfx::RenderContext, alpha: f32) {
+        let pos = (
+            util::lerp(self.prev_xpos, self.xpos, alpha) as i32,
+            util::lerp(self.prev_ypos, self.ypos, alpha) as i32,
+        );
         if self.killed {
-            context.draw_image(
-                (self.xpos as i32, self.ypos as i32),
-                &assets::SPR_PLAYER_DEAD,
-                0.0,
-                false,
-            );
+            context.draw_image(pos, &assets::SPR_PLAYER_DEAD, 0.0, false);
             return;
         }
 
@@ -278,18 +459,13 @@ impl entity::Entity for Player {
             } else {
                 &assets::SPR_PLAYER_CLIMB2
             };
-            context.draw_image((self.xpos as i32, self.ypos as i32), sprite, 0.0, false);
+            context.draw_image(pos, sprite, 0.0, false);
             return;
         }
 
         if !self.bow_drawn {
             // Draw bow on back
-            context.draw_image(
-                (self.xpos as i32, self.ypos as i32),
-                &assets::SPR_PLAYER_BOW_ON_BACK,
-                0.0,
-                self.facing_left,
-            );
+            context.draw_image(pos, &assets::SPR_PLAYER_BOW_ON_BACK, 0.0, self.facing_left);
         }
 
         let body_image = if !self.on_ground {
@@ -305,12 +481,7 @@ impl entity::Entity for Player {
             &assets::SPR_PLAYER_BODY_IDLE
         };
 
-        context.draw_image(
-            (self.xpos as i32, self.ypos as i32),
-            body_image,
-            0.0,
-            self.facing_left,
-        );
+        context.draw_image(pos, body_image, 0.0, self.facing_left);
 
         if self.bow_drawn {
             let bow_angle = if self.facing_left {
@@ -319,7 +490,7 @@ impl entity::Entity for Player {
                 self.bow_angle
             };
             context.draw_image(
-                (self.xpos as i32, self.ypos as i32),
+                pos,
                 &assets::SPR_PLAYER_BOW_DRAWN,
                 bow_angle,
                 self.facing_left,
@@ -336,12 +507,7 @@ impl entity::Entity for Player {
                 &assets::SPR_PLAYER_ARMS_IDLE
             };
 
-            context.draw_image(
-                (self.xpos as i32, self.ypos as i32),
-                arms_image,
-                0.0,
-                self.facing_left,
-            );
+            context.draw_image(pos, arms_image, 0.0, self.facing_left);
         }
     }
 
@@ -360,6 +526,10 @@ impl entity::Entity for Player {
         }
     }
 
+    fn get_vertical_velocity(&self) -> f32 {
+        self.yvec
+    }
+
     fn get_collision_class(&self) -> u32 {
         COLL_PLAYER
     }
@@ -368,12 +538,22 @@ impl entity::Entity for Player {
         COLL_MISSILE | COLL_ENEMY
     }
 
-    fn collide(&mut self, _other: &dyn entity::Entity) {
-        // XXX check type
-        if !self.killed {
-            self.killed = true;
-            audio::play_effect(assets::SFX_DEATH);
+    fn collide(&mut self, contact: &entity::Contact) {
+        if self.killed {
+            return;
         }
+
+        // Landing on top of an enemy kills it (see Bat::collide) and
+        // bounces the player back up, rather than killing the player.
+        if contact.other_class == COLL_ENEMY && contact.landed && contact.mtv.1 < 0 {
+            self.ypos += contact.mtv.1 as f32;
+            self.yvec = -STOMP_BOUNCE_SPEED;
+            self.jump_counter = MAX_JUMP_COUNTER;
+            return;
+        }
+
+        self.killed = true;
+        audio::play_effect(assets::SFX_DEATH);
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -381,14 +561,23 @@ impl entity::Entity for Player {
     }
 }
 
+// Distance from the arrow's origin to its tip.
+const ARROW_TIP_OFFSET: f32 = 14.0;
+
+// Fraction of the arrow's speed lost per second while the tip is in water.
+const ARROW_WATER_DRAG: f32 = 4.0;
+
 pub struct Arrow {
     xpos: f32,
     ypos: f32,
+    prev_xpos: f32,
+    prev_ypos: f32,
     xvec: f32,
     yvec: f32,
     angle: f32,
     wobble: f32,
     collided: bool,
+    heavy: bool, // Breaks breakable tiles
 }
 
 impl Arrow {
@@ -396,41 +585,94 @@ impl Arrow {
         Arrow {
             xpos,
             ypos,
+            prev_xpos: xpos,
+            prev_ypos: ypos,
             xvec: angle.cos() * velocity,
             yvec: angle.sin() * velocity,
             angle,
             wobble: 0.0,
             collided: false,
+            heavy: false,
+        }
+    }
+
+    pub fn new_heavy(xpos: f32, ypos: f32, angle: f32, velocity: f32) -> Arrow {
+        Arrow {
+            heavy: true,
+            ..Arrow::new(xpos, ypos, angle, velocity)
         }
     }
 }
 
 impl entity::Entity for Arrow {
-    fn update(
-        &mut self,
-        d_t: f32,
-        _new_entities: &mut Vec<Box<dyn entity::Entity>>,
-        _buttons: u32,
-        tile_map: &tilemap::TileMap,
-        _player_rect: &util::Rect<i32>,
-    ) {
-        if tile_map.is_solid(self.xpos as i32, self.ypos as i32) {
+    fn update(&mut self, ctx: &mut entity::UpdateContext) {
+        self.prev_xpos = self.xpos;
+        self.prev_ypos = self.ypos;
+
+        // Only the tip of the arrow collides with the walls. Sweep it along
+        // the path so fast arrows can't pass through thin walls.
+        let tip_x = self.angle.cos() * ARROW_TIP_OFFSET;
+        let tip_y = self.angle.sin() * ARROW_TIP_OFFSET;
+        let tip_rect = util::Rect::<f32>::new(self.xpos + tip_x, self.ypos + tip_y, 0.0, 0.0);
+        if ctx
+            .tile_map
+            .is_solid(tip_rect.left as i32, tip_rect.top as i32)
+        {
             self.collided = true;
+            return;
+        }
+
+        let start = (tip_rect.left, tip_rect.top);
+        let delta = (self.xvec * ctx.d_t, self.yvec * ctx.d_t);
+        let (tip_rect, collision) = ctx.tile_map.move_rect(&tip_rect, delta);
+        self.xpos = tip_rect.left - tip_x;
+        self.ypos = tip_rect.top - tip_y;
+        if collision.any() || ctx.tile_map.is_inside_slope(tip_rect.left, tip_rect.top) {
+            self.collided = true;
+        }
+
+        if collision.any() && self.heavy {
+            let end = (start.0 + delta.0, start.1 + delta.1);
+            if let Some(hit) = ctx.tile_map.raycast(start, end) {
+                if ctx.tile_map.is_breakable_tile(hit.tile_x, hit.tile_y) {
+                    ctx.tile_edits.push(tilemap::TileEdit {
+                        x: hit.tile_x,
+                        y: hit.tile_y,
+                        tile: 0,
+                    });
+                    audio::play_effect(assets::SFX_BREAK);
+                }
+            }
+        }
+
+        if ctx
+            .tile_map
+            .is_water(tip_rect.left as i32, tip_rect.top as i32)
+        {
+            let drag = 1.0 - (ARROW_WATER_DRAG * ctx.d_t).min(1.0);
+            self.xvec *= drag;
+            self.yvec *= drag;
         }
 
-        self.xpos += self.xvec * d_t;
-        self.ypos += self.yvec * d_t;
         self.angle = self.yvec.atan2(self.xvec);
         if self.yvec < 500.0 {
-            self.yvec += GRAVITY * d_t;
+            self.yvec += GRAVITY * ctx.d_t;
         }
 
-        self.wobble += d_t * 10.0;
+        self.wobble += ctx.d_t * 10.0;
     }
 
-    fn draw(&self, context: &mut gfx::RenderContext) {
+    fn is_transient(&self) -> bool {
+        true
+    }
+
+    fn draw(&self, context: &mut gfx::RenderContext, alpha: f32) {
+        let pos = (
+            util::lerp(self.prev_xpos, self.xpos, alpha) as i32,
+            util::lerp(self.prev_ypos, self.ypos, alpha) as i32,
+        );
         context.draw_image(
-            (self.xpos as i32, self.ypos as i32),
+            pos,
             &assets::SPR_ARROW,
             self.angle + self.wobble.sin() * 0.1,
             false,
@@ -444,8 +686,8 @@ impl entity::Entity for Arrow {
     fn get_bounding_box(&self) -> util::Rect<i32> {
         // We only track the tip of the arrow
         util::Rect::<i32>::new(
-            (self.xpos + self.angle.cos() * 14.0) as i32,
-            (self.ypos + self.angle.sin() * 14.0) as i32,
+            (self.xpos + self.angle.cos() * ARROW_TIP_OFFSET) as i32,
+            (self.ypos + self.angle.sin() * ARROW_TIP_OFFSET) as i32,
             4,
             4,
         )
@@ -459,7 +701,7 @@ impl entity::Entity for Arrow {
         !COLL_MISSILE
     }
 
-    fn collide(&mut self, _other: &dyn entity::Entity) {
+    fn collide(&mut self, _contact: &entity::Contact) {
         self.collided = true;
     }
 
@@ -471,6 +713,8 @@ impl entity::Entity for Arrow {
 pub struct Balloon {
     xpos: f32,
     ypos: f32,
+    prev_xpos: f32,
+    prev_ypos: f32,
     buoyancy: f32,
     popped: bool,
 }
@@ -480,6 +724,8 @@ impl Balloon {
         Balloon {
             xpos: x,
             ypos: y,
+            prev_xpos: x,
+            prev_ypos: y,
             buoyancy: 0.0,
             popped: false,
         }
@@ -487,25 +733,20 @@ impl Balloon {
 }
 
 impl entity::Entity for Balloon {
-    fn update(
-        &mut self,
-        d_t: f32,
-        _new_entities: &mut Vec<Box<dyn entity::Entity>>,
-        _buttons: u32,
-        _tile_map: &tilemap::TileMap,
-        _player_rect: &util::Rect<i32>,
-    ) {
-        self.buoyancy += d_t;
+    fn update(&mut self, ctx: &mut entity::UpdateContext) {
+        self.prev_xpos = self.xpos;
+        self.prev_ypos = self.ypos;
+
+        self.buoyancy += ctx.d_t;
         self.ypos += self.buoyancy.sin() * 0.5;
     }
 
-    fn draw(&self, context: &mut gfx::RenderContext) {
-        context.draw_image(
-            (self.xpos as i32, self.ypos as i32),
-            &assets::SPR_BALLOON,
-            0.0,
-            false,
+    fn draw(&self, context: &mut gfx::RenderContext, alpha: f32) {
+        let pos = (
+            util::lerp(self.prev_xpos, self.xpos, alpha) as i32,
+            util::lerp(self.prev_ypos, self.ypos, alpha) as i32,
         );
+        context.draw_image(pos, &assets::SPR_BALLOON, 0.0, false);
     }
 
     fn is_live(&self) -> bool {
@@ -524,7 +765,7 @@ impl entity::Entity for Balloon {
         util::Rect::<i32>::new(self.xpos as i32 - 10, self.ypos as i32 - 15, 20, 30)
     }
 
-    fn collide(&mut self, _other: &dyn entity::Entity) {
+    fn collide(&mut self, _contact: &entity::Contact) {
         self.popped = true;
         audio::play_effect(assets::SFX_POP);
         // XXX start an animation
@@ -535,13 +776,42 @@ impl entity::Entity for Balloon {
     }
 }
 
+// Default for how close the player has to be for the bat to notice them.
+// This can be changed in the map editor with the chase_range property.
+const BAT_CHASE_RANGE: i32 = 300;
+const BAT_CHASE_SPEED: f32 = 60.0;
+
+// Limits how much time pathfinding can take each step. Longer searches are
+// spread over multiple steps.
+const MAX_PATH_NODES_PER_STEP: usize = 64;
+
 pub struct Bat {
     xpos: f32,
     ypos: f32,
+    prev_xpos: f32,
+    prev_ypos: f32,
     anim_frame: i32,
     anim_counter: i32,
     killed: bool,
-    rng: ThreadRng,
+    chase_range: i32,
+
+    // Entity being chased, if any.
+    target: Option<entity::EntityId>,
+
+    // The path is recomputed when the target moves into a different tile.
+    // Until the new search finishes, the bat follows the old path.
+    path_goal: Option<pathfind::TilePos>,
+    search: Option<pathfind::PathSearch>,
+
+    // Tiles left to visit, in reverse order (so the next one can be popped
+    // off the end).
+    path: Vec<pathfind::TilePos>,
+}
+
+struct SavedBat {
+    xpos: f32,
+    ypos: f32,
+    target: Option<entity::EntityId>,
 }
 
 impl Bat {
@@ -549,23 +819,101 @@ impl Bat {
         Bat {
             xpos,
             ypos,
+            prev_xpos: xpos,
+            prev_ypos: ypos,
             anim_frame: 0,
             anim_counter: 0,
             killed: false,
-            rng: rand::rng(),
+            chase_range: BAT_CHASE_RANGE,
+            target: None,
+            path_goal: None,
+            search: None,
+            path: Vec::new(),
+        }
+    }
+
+    pub fn from_object(object: &tilemap::MapObject) -> Bat {
+        let mut bat = Bat::new(object.rect.left as f32, object.rect.top as f32);
+        if let Some(range) = object.get_int("chase_range") {
+            bat.chase_range = range;
+        }
+
+        bat
+    }
+
+    fn is_in_range(&self, rect: &util::Rect<i32>) -> bool {
+        (rect.left - self.xpos as i32).abs() < self.chase_range
+            && (rect.top - self.ypos as i32).abs() < self.chase_range
+    }
+
+    fn chase(&mut self, d_t: f32, tile_map: &tilemap::TileMap, target_center: (f32, f32)) {
+        let target_tile = to_tile_pos(target_center);
+        if self.path_goal != Some(target_tile) {
+            self.path_goal = Some(target_tile);
+            self.search = Some(pathfind::PathSearch::new(
+                to_tile_pos((self.xpos, self.ypos)),
+                target_tile,
+                pathfind::Movement::Flying,
+            ));
+        }
+
+        if let Some(search) = &mut self.search {
+            let new_path = match search.step(tile_map, MAX_PATH_NODES_PER_STEP) {
+                pathfind::PathStatus::Searching => None,
+                pathfind::PathStatus::Found(path) => Some(path.iter().rev().copied().collect()),
+                pathfind::PathStatus::NoPath => Some(Vec::new()),
+            };
+
+            if let Some(path) = new_path {
+                self.path = path;
+                self.search = None;
+            }
+        }
+
+        // Head for the center of the next tile in the path. Once in the
+        // same tile as the target (or if there's no path), go straight for it.
+        let waypoint = match self.path.last() {
+            Some(&(x, y)) => (
+                (x as f32 + 0.5) * tilemap::TILE_SIZE_F,
+                (y as f32 + 0.5) * tilemap::TILE_SIZE_F,
+            ),
+            None => target_center,
+        };
+
+        let dx = waypoint.0 - self.xpos;
+        let dy = waypoint.1 - self.ypos;
+        let distance = (dx * dx + dy * dy).sqrt();
+        let step = BAT_CHASE_SPEED * d_t;
+        if distance <= step {
+            self.xpos = waypoint.0;
+            self.ypos = waypoint.1;
+            self.path.pop();
+        } else {
+            self.xpos += dx / distance * step;
+            self.ypos += dy / distance * step;
         }
     }
 }
 
+fn get_center(rect: &util::Rect<i32>) -> (f32, f32) {
+    (
+        (rect.left + rect.width / 2) as f32,
+        (rect.top + rect.height / 2) as f32,
+    )
+}
+
+fn to_tile_pos((x, y): (f32, f32)) -> pathfind::TilePos {
+    (
+        (x / tilemap::TILE_SIZE_F).floor() as i32,
+        (y / tilemap::TILE_SIZE_F).floor() as i32,
+    )
+}
+
 impl entity::Entity for Bat {
-    fn update(
-        &mut self,
-        d_t: f32,
-        _new_entities: &mut Vec<Box<dyn entity::Entity>>,
-        _buttons: u32,
-        _tile_map: &tilemap::TileMap,
-        player_rect: &util::Rect<i32>,
-    ) {
+    fn update(&mut self, ctx: &mut entity::UpdateContext) {
+        self.prev_xpos = self.xpos;
+        self.prev_ypos = self.ypos;
+
         if self.anim_counter == 0 {
             self.anim_counter = 10;
             self.anim_frame = 1 - self.anim_frame;
@@ -573,46 +921,80 @@ impl entity::Entity for Bat {
             self.anim_counter -= 1;
         }
 
-        let dx = player_rect.left - self.xpos as i32;
-        let dy = player_rect.top - self.ypos as i32;
-
-        if dx.abs() < 300 && dy.abs() < 300 {
-            // Follow player
-            let mut xdir: f32 = if dx > 0 { 30.0 } else { -30.0 };
-
-            let mut ydir: f32 = if dy > 0 { 30.0 } else { -30.0 };
-
-            if self.rng.random::<f32>() < 0.2 {
-                xdir = -xdir;
-            }
-
-            if self.rng.random::<f32>() < 0.2 {
-                ydir = -ydir;
-            }
+        // Start chasing the player when it comes into view. After that, the
+        // bat can follow it around walls.
+        if self.target.is_none() {
+            self.target = ctx.world.player_id().filter(|&id| {
+                ctx.world.get_bounding_box(id).is_some_and(|rect| {
+                    self.is_in_range(&rect)
+                        && ctx
+                            .tile_map
+                            .line_of_sight((self.xpos, self.ypos), get_center(&rect))
+                })
+            });
+        }
 
-            self.xpos += xdir * d_t;
-            self.ypos += ydir * d_t;
+        // Give up if the target has despawned or gotten too far away.
+        let target_rect = self
+            .target
+            .and_then(|id| ctx.world.get_bounding_box(id))
+            .filter(|rect| self.is_in_range(rect));
+        if let Some(rect) = target_rect {
+            self.chase(ctx.d_t, ctx.tile_map, get_center(&rect));
         } else {
-            let xdir = if self.rng.random::<f32>() < 0.5 {
+            self.target = None;
+            self.path_goal = None;
+            self.search = None;
+            self.path.clear();
+
+            let xdir = if ctx.rng.random::<f32>() < 0.5 {
                 -30.0
             } else {
                 30.0
             };
 
-            let ydir = if self.rng.random::<f32>() < 0.5 {
+            let ydir = if ctx.rng.random::<f32>() < 0.5 {
                 -30.0
             } else {
                 30.0
             };
 
-            self.xpos += xdir * d_t;
-            self.ypos += ydir * d_t;
+            self.xpos += xdir * ctx.d_t;
+            self.ypos += ydir * ctx.d_t;
         }
     }
 
-    fn draw(&self, context: &mut gfx::RenderContext) {
+    fn tiles_changed(&mut self, _region: &util::Rect<i32>) {
+        // There may be a better (or no) path now, so search again.
+        self.path_goal = None;
+    }
+
+    // Keep where the bat was and who it was chasing when it is despawned.
+    // The path is searched for again.
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        Some(Box::new(SavedBat {
+            xpos: self.xpos,
+            ypos: self.ypos,
+            target: self.target,
+        }))
+    }
+
+    fn restore_state(&mut self, state: Box<dyn Any>) {
+        let saved = state.downcast::<SavedBat>().unwrap();
+        self.xpos = saved.xpos;
+        self.ypos = saved.ypos;
+        self.prev_xpos = saved.xpos;
+        self.prev_ypos = saved.ypos;
+        self.target = saved.target;
+    }
+
+    fn draw(&self, context: &mut gfx::RenderContext, alpha: f32) {
+        let pos = (
+            util::lerp(self.prev_xpos, self.xpos, alpha) as i32,
+            util::lerp(self.prev_ypos, self.ypos, alpha) as i32,
+        );
         context.draw_image(
-            (self.xpos as i32, self.ypos as i32),
+            pos,
             if self.anim_frame == 0 {
                 &assets::SPR_BAT1
             } else {
@@ -632,14 +1014,19 @@ impl entity::Entity for Bat {
     }
 
     fn get_collision_mask(&self) -> u32 {
-        COLL_MISSILE
+        COLL_MISSILE | COLL_PLAYER
     }
 
     fn get_bounding_box(&self) -> util::Rect<i32> {
         util::Rect::<i32>::new(self.xpos as i32 - 10, self.ypos as i32 - 10, 20, 20)
     }
 
-    fn collide(&mut self, _other: &dyn entity::Entity) {
+    fn collide(&mut self, contact: &entity::Contact) {
+        // The player can only hurt bats by landing on them.
+        if contact.other_class == COLL_PLAYER && !(contact.landed && contact.mtv.1 > 0) {
+            return;
+        }
+
         if !self.killed {
             self.killed = true;
             audio::play_effect(assets::SFX_BAT_DEATH);
@@ -650,3 +1037,545 @@ impl entity::Entity for Bat {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden;
+    use crate::test_util::{create_headless_engine, get_asset_dir};
+    use engine::GameEngine;
+    use std::path::Path;
+
+    fn get_player(eng: &GameEngine) -> &Player {
+        eng.get_player()
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Player>()
+            .unwrap()
+    }
+
+    fn run_frames(eng: &mut GameEngine, buttons: u32, count: u32) {
+        eng.buttons = buttons;
+        for _ in 0..count {
+            eng.update_input(engine::TIME_STEP);
+            eng.update_entities(engine::TIME_STEP);
+        }
+    }
+
+    #[test]
+    fn test_player_idle() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        let (start_x, start_y) = (get_player(&eng).xpos, get_player(&eng).ypos);
+        assert!(get_player(&eng).on_ground);
+
+        run_frames(&mut eng, 0, 60);
+        let player = get_player(&eng);
+        assert!(player.on_ground);
+        assert_eq!(player.xpos, start_x);
+        assert_eq!(player.ypos, start_y);
+    }
+
+    #[test]
+    fn test_player_run() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        let start_x = get_player(&eng).xpos;
+
+        run_frames(&mut eng, entity::CONTROL_RIGHT, 30);
+        let player = get_player(&eng);
+        assert!(player.is_running);
+        assert!(!player.facing_left);
+        assert!((player.xpos - (start_x + 75.0)).abs() < 1.0);
+
+        run_frames(&mut eng, entity::CONTROL_LEFT, 30);
+        let player = get_player(&eng);
+        assert!(player.facing_left);
+        assert!((player.xpos - start_x).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_player_jump() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        let start_y = get_player(&eng).ypos;
+
+        run_frames(&mut eng, entity::CONTROL_JUMP, 10);
+        assert!(!get_player(&eng).on_ground);
+        assert!(get_player(&eng).ypos < start_y);
+
+        run_frames(&mut eng, 0, 120);
+        assert!(get_player(&eng).on_ground);
+        assert_eq!(get_player(&eng).ypos, start_y);
+    }
+
+    #[test]
+    fn test_fire_arrow() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        audio::take_played_effects();
+
+        run_frames(&mut eng, entity::CONTROL_FIRE, 15);
+        assert!(get_player(&eng).bow_drawn);
+        run_frames(&mut eng, 0, 1);
+        assert!(!get_player(&eng).bow_drawn);
+        assert!(eng.entities.iter().any(|e| e.as_any().is::<Arrow>()));
+        assert_eq!(audio::take_played_effects(), vec![assets::SFX_ARROW]);
+    }
+
+    #[test]
+    fn test_stomp_bat() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        run_frames(&mut eng, entity::CONTROL_JUMP, 10);
+        while get_player(&eng).yvec <= 0.0 {
+            run_frames(&mut eng, 0, 1);
+        }
+
+        let player = get_player(&eng);
+        let bat_id = eng.spawn(Box::new(Bat::new(player.xpos, player.ypos + 40.0)));
+        while eng.get_entity(bat_id).is_some() {
+            run_frames(&mut eng, 0, 1);
+        }
+
+        assert!(!get_player(&eng).killed);
+        assert!(get_player(&eng).yvec < 0.0);
+    }
+
+    #[test]
+    fn test_rise_into_bat_from_above() {
+        // The player is above the bat, but still moving up, so this isn't a
+        // stomp. Only the player should die.
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        run_frames(&mut eng, entity::CONTROL_JUMP, 2);
+        let player = get_player(&eng);
+        assert!(player.yvec < 0.0);
+        let bat_id = eng.spawn(Box::new(Bat::new(player.xpos, player.ypos + 18.0)));
+        run_frames(&mut eng, 0, 1);
+
+        assert!(get_player(&eng).killed);
+        assert!(eng.get_entity(bat_id).is_some());
+    }
+
+    #[test]
+    fn test_touch_bat() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        let player = get_player(&eng);
+        let bat_id = eng.spawn(Box::new(Bat::new(player.xpos + 30.0, player.ypos)));
+        while !get_player(&eng).killed {
+            run_frames(&mut eng, 0, 1);
+        }
+
+        assert!(eng.get_entity(bat_id).is_some());
+    }
+
+    // Replace the player with one standing in the given tile.
+    fn spawn_player_at(eng: &mut GameEngine, tile_x: i32, tile_y: i32) {
+        let id = eng.spawn(Box::new(Player::new(
+            ((tile_x * tilemap::TILE_SIZE) + tilemap::TILE_SIZE / 2) as f32,
+            (tile_y * tilemap::TILE_SIZE) as f32,
+        )));
+        eng.player_id = Some(id);
+    }
+
+    fn get_player_feet(eng: &GameEngine) -> f32 {
+        let player = get_player(eng);
+        player.ypos + player.ground_offset as f32
+    }
+
+    // Puts a new player in the pool in the starting room.
+    fn spawn_swimming_player(eng: &mut GameEngine) {
+        spawn_player_at(eng, 8, 12);
+        run_frames(eng, 0, 1);
+        assert!(get_player(eng).swimming);
+    }
+
+    #[test]
+    fn test_swim() {
+        let mut eng = create_headless_engine();
+        spawn_swimming_player(&mut eng);
+
+        // Sinks slowly
+        run_frames(&mut eng, 0, 30);
+        let player = get_player(&eng);
+        assert!(player.yvec > 0.0 && player.yvec < SWIM_GRAVITY / SWIM_DRAG + 1.0);
+        let start_y = player.ypos;
+
+        run_frames(&mut eng, entity::CONTROL_UP, 30);
+        let player = get_player(&eng);
+        assert!(player.ypos < start_y);
+        assert!(!player.killed);
+    }
+
+    #[test]
+    fn test_drown() {
+        let mut eng = create_headless_engine();
+        spawn_swimming_player(&mut eng);
+        audio::take_played_effects();
+
+        let frames_per_second = (1.0 / engine::TIME_STEP) as u32;
+        run_frames(&mut eng, 0, (MAX_BREATH as u32 - 1) * frames_per_second);
+        assert!(!get_player(&eng).killed);
+
+        run_frames(&mut eng, 0, 2 * frames_per_second);
+        assert!(get_player(&eng).killed);
+        assert_eq!(audio::take_played_effects(), vec![assets::SFX_DEATH]);
+    }
+
+    #[test]
+    fn test_walk_over_slopes() {
+        // There is a 45 degree slope up at tile 30, flat at 31-32, and a
+        // slope back down at 33, then 22.5 degree slopes up at 36-37 and down
+        // at 39-40.
+        let mut eng = create_headless_engine();
+        spawn_player_at(&mut eng, 29, 17);
+        run_frames(&mut eng, 0, 10);
+        let floor = get_player_feet(&eng);
+
+        let mut highest = floor;
+        while get_player(&eng).xpos < (41 * tilemap::TILE_SIZE) as f32 {
+            run_frames(&mut eng, entity::CONTROL_RIGHT, 1);
+            assert!(get_player(&eng).on_ground);
+            highest = highest.min(get_player_feet(&eng));
+        }
+
+        assert_eq!(highest, floor - tilemap::TILE_SIZE_F);
+        assert_eq!(get_player_feet(&eng), floor);
+
+        // And back
+        while get_player(&eng).xpos > (30 * tilemap::TILE_SIZE) as f32 {
+            run_frames(&mut eng, entity::CONTROL_LEFT, 1);
+            assert!(get_player(&eng).on_ground);
+        }
+
+        assert_eq!(get_player_feet(&eng), floor);
+    }
+
+    #[test]
+    fn test_one_way_platform() {
+        // Platform is in tiles 42-43
+        let mut eng = create_headless_engine();
+        spawn_player_at(&mut eng, 41, 17);
+        run_frames(&mut eng, 0, 10);
+        let floor = get_player_feet(&eng);
+
+        // Jump up through it and land on top
+        run_frames(&mut eng, entity::CONTROL_JUMP | entity::CONTROL_RIGHT, 10);
+        run_frames(&mut eng, entity::CONTROL_RIGHT, 20);
+        run_frames(&mut eng, 0, 60);
+        assert!(get_player(&eng).on_ground);
+        assert_eq!(get_player_feet(&eng), floor - tilemap::TILE_SIZE_F);
+
+        // Drop back through
+        run_frames(&mut eng, entity::CONTROL_DOWN | entity::CONTROL_JUMP, 1);
+        run_frames(&mut eng, 0, 60);
+        assert!(get_player(&eng).on_ground);
+        assert!((get_player_feet(&eng) - floor).abs() < 1.0);
+    }
+
+    fn count_bats(eng: &GameEngine) -> usize {
+        eng.entities
+            .iter()
+            .filter(|e| e.as_any().downcast_ref::<Bat>().is_some())
+            .count()
+    }
+
+    #[test]
+    fn test_bat_ambush() {
+        // The trigger covers tiles 41-44, and only fires once.
+        let mut eng = create_headless_engine();
+        for (name, trigger_fn) in TRIGGER_LIST {
+            eng.register_trigger(name, *trigger_fn);
+        }
+
+        spawn_player_at(&mut eng, 41, 17);
+        eng.center_camera();
+        let start_bats = count_bats(&eng);
+        run_frames(&mut eng, 0, 10);
+        assert_eq!(count_bats(&eng), start_bats + 2);
+
+        run_frames(&mut eng, entity::CONTROL_LEFT, 60);
+        assert!(get_player(&eng).xpos < 2624.0);
+        run_frames(&mut eng, entity::CONTROL_RIGHT, 60);
+        assert!(get_player(&eng).xpos > 2624.0);
+        assert_eq!(count_bats(&eng), start_bats + 2);
+    }
+
+    fn reload_map(
+        eng: &mut GameEngine,
+        _trigger: &tilemap::MapObject,
+        _event: engine::trigger::TriggerEvent,
+    ) {
+        eng.load_tile_map("map");
+    }
+
+    #[test]
+    fn test_trigger_replaces_map() {
+        // Two triggers at the end of the list fire in the same step. The
+        // first one reloads the map, which removes both of them.
+        let mut eng = create_headless_engine();
+        eng.register_trigger("Reload", reload_map);
+        let num_triggers = eng.tile_map.triggers.len();
+        spawn_player_at(&mut eng, 10, 10);
+        let player_rect = eng.get_player().unwrap().get_bounding_box();
+        for _ in 0..2 {
+            let mut trigger = tilemap::MapObject::new("Trigger", 0, 0);
+            trigger.name = String::from("Reload");
+            trigger.rect = player_rect;
+            eng.tile_map.triggers.push(trigger);
+        }
+
+        run_frames(&mut eng, 0, 1);
+        assert_eq!(eng.tile_map.triggers.len(), num_triggers);
+    }
+
+    #[test]
+    fn test_change_map() {
+        let mut eng = create_headless_engine();
+        eng.create_entities();
+        eng.tile_map.set_tile(27, 16, 0);
+        spawn_player_at(&mut eng, 43, 17);
+        run_frames(&mut eng, 0, 10);
+
+        // Walk into the warp at tile 45. The player arrives at the entrance,
+        // and the entities are replaced with the ones in the cave that are
+        // near the camera (the balloon, but not the bat).
+        run_frames(&mut eng, entity::CONTROL_RIGHT, 40);
+        assert_eq!(eng.tile_map.width, 24);
+        assert_eq!(eng.entities.len(), 2);
+        assert_eq!(count_bats(&eng), 0);
+        assert!(get_player(&eng).xpos < 256.0);
+
+        // The warp back doesn't fire until the player leaves and comes back.
+        run_frames(&mut eng, entity::CONTROL_RIGHT, 30);
+        assert_eq!(eng.tile_map.width, 24);
+        for _ in 0..60 {
+            run_frames(&mut eng, entity::CONTROL_LEFT, 1);
+            if eng.tile_map.width != 24 {
+                break;
+            }
+        }
+
+        assert_eq!(eng.tile_map.width, 100);
+        assert_eq!(get_player(&eng).xpos, 2816.0);
+        assert_eq!(eng.tile_map.get_tile(27, 16), 0);
+    }
+
+    #[test]
+    fn test_heavy_arrow() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+
+        // Drawing the bow all the way fires a heavy arrow
+        run_frames(&mut eng, entity::CONTROL_FIRE, 30);
+        run_frames(&mut eng, 0, 1);
+        assert!(eng.entities.iter().any(|e| e
+            .as_any()
+            .downcast_ref::<Arrow>()
+            .is_some_and(|arrow| arrow.heavy)));
+
+        // There are breakable bricks at tile 27, rows 16-17
+        let tile_center = |x: i32, y: i32| {
+            (
+                (x * tilemap::TILE_SIZE + tilemap::TILE_SIZE / 2) as f32,
+                (y * tilemap::TILE_SIZE + tilemap::TILE_SIZE / 2) as f32,
+            )
+        };
+        let (x, y) = tile_center(26, 16);
+        eng.spawn(Box::new(Arrow::new(x, y, 0.0, 2000.0)));
+        run_frames(&mut eng, 0, 10);
+        assert!(eng.tile_map.is_solid_tile(27, 16));
+
+        audio::take_played_effects();
+        let (x, y) = tile_center(26, 17);
+        eng.spawn(Box::new(Arrow::new_heavy(x, y, 0.0, 2000.0)));
+        run_frames(&mut eng, 0, 10);
+        assert!(eng.tile_map.is_solid_tile(27, 16));
+        assert!(!eng.tile_map.is_solid_tile(27, 17));
+        assert_eq!(audio::take_played_effects(), vec![assets::SFX_BREAK]);
+        assert_eq!(
+            eng.tile_map.get_modifications(),
+            vec![tilemap::TileEdit {
+                x: 27,
+                y: 17,
+                tile: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_object_properties() {
+        let mut eng = create_headless_engine();
+        // Create everything, regardless of where the camera is.
+        eng.spawn_margin = 10000;
+        eng.create_entities();
+        let bats: Vec<&Bat> = eng
+            .entities
+            .iter()
+            .filter_map(|e| e.as_any().downcast_ref::<Bat>())
+            .collect();
+        assert!(bats.iter().any(|bat| bat.chase_range == 400));
+        assert!(bats.iter().any(|bat| bat.chase_range == BAT_CHASE_RANGE));
+    }
+
+    #[test]
+    fn test_spawn_near_camera() {
+        let mut eng = create_headless_engine();
+        // The bat at the top right of the first room (x 1590, with a chase
+        // range of 400) is created when the camera moves near it, and removed
+        // when it moves away.
+        let has_far_bat = |eng: &GameEngine| {
+            eng.entities.iter().any(|e| {
+                e.as_any()
+                    .downcast_ref::<Bat>()
+                    .is_some_and(|bat| bat.chase_range == 400)
+            })
+        };
+
+        eng.create_entities();
+        assert!(!has_far_bat(&eng));
+        eng.x_scroll = 1200;
+        run_frames(&mut eng, 0, 1);
+        assert!(has_far_bat(&eng));
+
+        eng.x_scroll = 0;
+        eng.y_scroll = 800;
+        run_frames(&mut eng, 0, 1);
+        assert!(!has_far_bat(&eng));
+    }
+
+    fn get_far_bat(eng: &mut GameEngine) -> &mut Bat {
+        eng.entities
+            .iter_mut()
+            .find_map(|e| {
+                let any: &mut dyn Any = e.as_mut();
+                any.downcast_mut::<Bat>()
+                    .filter(|bat| bat.chase_range == 400)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bat_state_kept_after_despawn() {
+        let mut eng = create_headless_engine();
+        eng.create_entities();
+        eng.x_scroll = 1200;
+        run_frames(&mut eng, 0, 1);
+        let bat = get_far_bat(&mut eng);
+        bat.xpos -= 100.0;
+        let xpos = bat.xpos;
+
+        // Move away so it is removed, then come back.
+        eng.x_scroll = 0;
+        eng.y_scroll = 800;
+        run_frames(&mut eng, 0, 1);
+        eng.x_scroll = 1200;
+        eng.y_scroll = 0;
+        run_frames(&mut eng, 0, 1);
+
+        // It only moves a little in one step.
+        assert!((get_far_bat(&mut eng).xpos - xpos).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_spawned_bats_not_despawned() {
+        let mut eng = create_headless_engine();
+        let bat_id = eng.spawn(Box::new(Bat::new(3000.0, 3000.0)));
+        run_frames(&mut eng, 0, 1);
+        assert!(eng.get_entity(bat_id).is_some());
+    }
+
+    #[test]
+    fn test_arrow_in_water() {
+        let mut eng = create_headless_engine();
+        let arrow_id = eng.spawn(Box::new(Arrow::new(480.0, 800.0, 0.0, 1000.0)));
+        run_frames(&mut eng, 0, 10);
+        let arrow = eng
+            .get_entity(arrow_id)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Arrow>()
+            .unwrap();
+        assert!(arrow.xvec < 700.0);
+    }
+
+    fn check_golden_image(context: &gfx::RenderContext, name: &str) {
+        let golden_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name);
+        let result = golden::check_golden_image(
+            context.framebuffer().unwrap(),
+            &golden_path,
+            &get_asset_dir().join("golden-failures"),
+            2,
+        );
+        if let Err(msg) = result {
+            panic!("{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_golden_tile_map() {
+        let mut eng = create_headless_engine();
+        eng.render_context = gfx::RenderContext::new_software(&get_asset_dir().join("atlas.png"));
+        run_frames(&mut eng, 0, 10);
+
+        eng.x_scroll = 0;
+        eng.y_scroll = 400;
+        eng.draw_world(1.0);
+        eng.render_context.render();
+        check_golden_image(&eng.render_context, "tile_map.png");
+    }
+
+    #[test]
+    fn test_golden_player_states() {
+        let mut context = gfx::RenderContext::new_software(&get_asset_dir().join("atlas.png"));
+
+        let states: &[fn(&mut Player)] = &[
+            |_| {},
+            |p| {
+                p.is_running = true;
+                p.run_frame = 0;
+            },
+            |p| {
+                p.is_running = true;
+                p.run_frame = 1;
+            },
+            |p| {
+                p.is_running = true;
+                p.run_frame = 2;
+            },
+            |p| p.on_ground = false,
+            |p| {
+                p.bow_drawn = true;
+                p.bow_angle = -0.5;
+            },
+            |p| p.facing_left = true,
+            |p| {
+                p.facing_left = true;
+                p.bow_drawn = true;
+                p.bow_angle = -0.5;
+            },
+            |p| p.climbing = true,
+            |p| {
+                p.climbing = true;
+                p.ypos += 32.0;
+            },
+            |p| p.killed = true,
+        ];
+
+        for (i, setup) in states.iter().enumerate() {
+            let mut player = Player::new(40.0 + i as f32 * 70.0, 100.0);
+            player.on_ground = true;
+            setup(&mut player);
+            player.prev_xpos = player.xpos;
+            player.prev_ypos = player.ypos;
+            entity::Entity::draw(&player, &mut context, 1.0);
+        }
+
+        context.render();
+        check_golden_image(&context, "player_states.png");
+    }
+}
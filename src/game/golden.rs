@@ -0,0 +1,133 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//
+// Support for golden image tests: compare a rendered frame (from the
+// software renderer) against a reference image checked into the repository.
+// To regenerate the reference images after an intentional change, run the
+// tests with UPDATE_GOLDEN_IMAGES=1 set in the environment.
+//
+
+use image::RgbaImage;
+use std::path::Path;
+
+// Compare image against the file at golden_path. Pixels match if every
+// channel differs by no more than tolerance. On mismatch, the actual image
+// and a diff image (mismatched pixels in red) are written into failure_dir
+// and an error describing the failure is returned.
+pub fn check_golden_image(
+    actual: &RgbaImage,
+    golden_path: &Path,
+    failure_dir: &Path,
+    tolerance: u8,
+) -> Result<(), String> {
+    if std::env::var_os("UPDATE_GOLDEN_IMAGES").is_some() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        actual.save(golden_path).unwrap();
+        return Ok(());
+    }
+
+    let golden = match image::open(golden_path) {
+        Ok(img) => img.into_rgba8(),
+        Err(msg) => {
+            return Err(format!(
+                "Unable to read golden image {}: {} (set UPDATE_GOLDEN_IMAGES=1 to create it)",
+                golden_path.display(),
+                msg
+            ))
+        }
+    };
+
+    if golden.dimensions() != actual.dimensions() {
+        return Err(format!(
+            "Size mismatch for {}: expected {:?}, got {:?}",
+            golden_path.display(),
+            golden.dimensions(),
+            actual.dimensions()
+        ));
+    }
+
+    let (diff, mismatch_count) = diff_images(actual, &golden, tolerance);
+    if mismatch_count == 0 {
+        return Ok(());
+    }
+
+    let stem = golden_path.file_stem().unwrap().to_str().unwrap();
+    std::fs::create_dir_all(failure_dir).unwrap();
+    let actual_path = failure_dir.join(format!("{}-actual.png", stem));
+    let diff_path = failure_dir.join(format!("{}-diff.png", stem));
+    actual.save(&actual_path).unwrap();
+    diff.save(&diff_path).unwrap();
+
+    Err(format!(
+        "{} pixels differ from {}, see {} and {}",
+        mismatch_count,
+        golden_path.display(),
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+// Returns an image where mismatched pixels are red and matching ones are a
+// faded copy of the original (to make it easier to see where they are),
+// along with the number of mismatched pixels.
+fn diff_images(actual: &RgbaImage, golden: &RgbaImage, tolerance: u8) -> (RgbaImage, usize) {
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatch_count = 0;
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let expected = golden.get_pixel(x, y);
+        let matches = pixel
+            .0
+            .iter()
+            .zip(expected.0.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= tolerance);
+        if matches {
+            let luma = (pixel.0[0] as u32 + pixel.0[1] as u32 + pixel.0[2] as u32) / 3;
+            let faded = (128 + luma / 2) as u8;
+            diff.put_pixel(x, y, image::Rgba([faded, faded, faded, 255]));
+        } else {
+            mismatch_count += 1;
+            diff.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
+        }
+    }
+
+    (diff, mismatch_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_images() {
+        let mut golden = RgbaImage::new(4, 4);
+        let mut actual = golden.clone();
+        actual.put_pixel(1, 1, image::Rgba([3, 0, 0, 0]));
+        actual.put_pixel(2, 2, image::Rgba([0, 0, 10, 0]));
+
+        let (_, mismatch_count) = diff_images(&actual, &golden, 0);
+        assert_eq!(mismatch_count, 2);
+
+        let (diff, mismatch_count) = diff_images(&actual, &golden, 4);
+        assert_eq!(mismatch_count, 1);
+        assert_eq!(diff.get_pixel(2, 2).0, [255, 0, 0, 255]);
+        assert_ne!(diff.get_pixel(1, 1).0, [255, 0, 0, 255]);
+
+        golden.put_pixel(2, 2, image::Rgba([0, 0, 12, 0]));
+        let (_, mismatch_count) = diff_images(&actual, &golden, 4);
+        assert_eq!(mismatch_count, 0);
+    }
+}
@@ -0,0 +1,206 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::assets;
+use engine::scene::{Scene, SceneTransition};
+use engine::{audio, entity, gfx, ui, GameEngine};
+
+const NINE_TILE: [gfx::SpriteInfo; 9] = [
+    assets::SPR_9TILE_A,
+    assets::SPR_9TILE_B,
+    assets::SPR_9TILE_C,
+    assets::SPR_9TILE_D,
+    assets::SPR_9TILE_E,
+    assets::SPR_9TILE_F,
+    assets::SPR_9TILE_G,
+    assets::SPR_9TILE_H,
+    assets::SPR_9TILE_I,
+];
+
+pub struct GameplayScene {
+    _music: Option<sdl2::mixer::Music<'static>>,
+}
+
+impl GameplayScene {
+    pub fn new() -> GameplayScene {
+        GameplayScene { _music: None }
+    }
+}
+
+impl Scene for GameplayScene {
+    fn on_enter(&mut self, eng: &mut GameEngine) {
+        eng.create_entities();
+
+        self._music = audio::play_music("music_track1.mp3");
+    }
+
+    fn update(&mut self, eng: &mut GameEngine, d_t: f32) -> SceneTransition {
+        if eng.pressed(entity::CONTROL_MENU) {
+            return SceneTransition::Push(Box::new(PauseScene::new()));
+        }
+
+        eng.update_camera();
+        eng.tile_map.update(d_t);
+        eng.update_entities(d_t);
+
+        SceneTransition::None
+    }
+
+    fn draw(&self, eng: &mut GameEngine, alpha: f32) {
+        eng.draw_world(alpha);
+    }
+}
+
+pub struct PauseScene {
+    menu_anim: ui::Interpolator,
+    scale: f32,
+}
+
+impl PauseScene {
+    pub fn new() -> PauseScene {
+        PauseScene {
+            menu_anim: ui::Interpolator::new(0.0, ui::cubic_inout),
+            scale: 0.0,
+        }
+    }
+}
+
+impl Scene for PauseScene {
+    fn on_enter(&mut self, _eng: &mut GameEngine) {
+        audio::play_effect(assets::SFX_PAUSE);
+        audio::pause_music();
+        self.menu_anim.start(0.4, 0.0, 1.0);
+    }
+
+    fn on_exit(&mut self, _eng: &mut GameEngine) {
+        audio::resume_music();
+    }
+
+    fn update(&mut self, eng: &mut GameEngine, d_t: f32) -> SceneTransition {
+        self.scale = self.menu_anim.update(d_t);
+
+        if eng.pressed(entity::CONTROL_MENU) {
+            SceneTransition::Pop
+        } else {
+            SceneTransition::None
+        }
+    }
+
+    fn draw(&self, eng: &mut GameEngine, _alpha: f32) {
+        ui::draw_nine_tile(
+            &mut eng.render_context,
+            50,
+            20,
+            40 + (self.scale * 250.0) as i32,
+            40 + (self.scale * 350.0) as i32,
+            &NINE_TILE,
+        );
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::test_util;
+
+    fn create_headless_engine() -> GameEngine {
+        let mut eng = test_util::create_headless_engine();
+        for (name, trigger_fn) in entities::TRIGGER_LIST {
+            eng.register_trigger(name, *trigger_fn);
+        }
+
+        eng.push_scene(Box::new(GameplayScene::new()));
+        eng
+    }
+
+    fn get_entity_locations(eng: &GameEngine) -> Vec<(i32, i32)> {
+        eng.entities
+            .iter()
+            .map(|entity| {
+                let bounds = entity.get_bounding_box();
+                (bounds.left, bounds.top)
+            })
+            .collect()
+    }
+
+    fn run_frames(eng: &mut GameEngine, buttons: u32, count: u32) {
+        eng.buttons = buttons;
+        for _ in 0..count {
+            assert!(eng.run_frame());
+        }
+    }
+
+    fn has_arrow(eng: &GameEngine) -> bool {
+        eng.entities
+            .iter()
+            .any(|entity| entity.as_any().is::<entities::Arrow>())
+    }
+
+    #[test]
+    fn test_release_fire_while_paused() {
+        let mut eng = create_headless_engine();
+        run_frames(&mut eng, 0, 10);
+        run_frames(&mut eng, entity::CONTROL_FIRE, 15);
+        run_frames(&mut eng, entity::CONTROL_FIRE | entity::CONTROL_MENU, 1);
+
+        // Release fire while the pause menu is up, then unpause.
+        run_frames(&mut eng, 0, 5);
+        run_frames(&mut eng, entity::CONTROL_MENU, 1);
+        assert!(!has_arrow(&eng));
+        run_frames(&mut eng, 0, 1);
+        assert!(has_arrow(&eng));
+    }
+
+    #[test]
+    fn test_replay() {
+        const NUM_FRAMES: u32 = 600;
+        let path = std::env::temp_dir().join(format!("game-replay-{}.bin", std::process::id()));
+
+        let mut eng = create_headless_engine();
+        eng.seed_rng(1234);
+        eng.start_recording(&path).unwrap();
+        for frame in 0..NUM_FRAMES {
+            eng.buttons = match frame % 200 {
+                0..50 => entity::CONTROL_RIGHT,
+                50..60 => entity::CONTROL_JUMP | entity::CONTROL_RIGHT,
+                60..120 => entity::CONTROL_FIRE,
+                120..140 => entity::CONTROL_LEFT,
+                _ => 0,
+            };
+            assert!(eng.run_frame());
+        }
+
+        eng.stop_recording();
+        let recorded_locations = get_entity_locations(&eng);
+
+        // The seed should come from the recording, not from here.
+        let mut eng = create_headless_engine();
+        eng.seed_rng(5678);
+        eng.start_playback(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for _ in 0..NUM_FRAMES {
+            assert!(eng.is_playing_back());
+            assert!(eng.run_frame());
+        }
+
+        assert_eq!(get_entity_locations(&eng), recorded_locations);
+    }
+}
@@ -0,0 +1,45 @@
+//
+// Copyright 2025 Jeff Bush
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Shared setup for tests that run the game without a window.
+
+use crate::entities;
+use engine::GameEngine;
+use std::path::PathBuf;
+
+// Where build_assets.rs writes the maps and atlas.
+pub fn get_asset_dir() -> PathBuf {
+    PathBuf::from(env!("ASSET_DIR"))
+}
+
+// An engine with the first map loaded and the player at its start. Entities
+// can be created from the map with create_entities, but triggers are not
+// registered.
+pub fn create_headless_engine() -> GameEngine {
+    let mut eng = GameEngine::new_headless();
+    for (name, ctor) in entities::ENTITY_LIST {
+        eng.register_entity(name, *ctor);
+    }
+
+    eng.map_dir = get_asset_dir();
+    eng.load_tile_map("map");
+    eng.spawn_player(|start| {
+        Box::new(entities::Player::new(
+            start.rect.left as f32,
+            start.rect.top as f32,
+        ))
+    });
+    eng
+}